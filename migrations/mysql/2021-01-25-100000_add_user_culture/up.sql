@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN culture VARCHAR(35) NOT NULL DEFAULT 'en-US';
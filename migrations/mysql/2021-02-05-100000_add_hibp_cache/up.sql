@@ -0,0 +1,5 @@
+CREATE TABLE hibp_cache (
+  account_hash  CHAR(64) NOT NULL PRIMARY KEY,
+  response      MEDIUMTEXT,
+  created_at    DATETIME NOT NULL
+);
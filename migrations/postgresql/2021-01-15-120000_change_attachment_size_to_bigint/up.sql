@@ -0,0 +1 @@
+ALTER TABLE attachments ALTER COLUMN file_size TYPE BIGINT;
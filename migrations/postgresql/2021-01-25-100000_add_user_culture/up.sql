@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN culture TEXT NOT NULL DEFAULT 'en-US';
@@ -0,0 +1 @@
+DROP TABLE hibp_cache;
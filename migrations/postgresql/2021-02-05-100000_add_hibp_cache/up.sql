@@ -0,0 +1,5 @@
+CREATE TABLE hibp_cache (
+  account_hash  CHAR(64) NOT NULL PRIMARY KEY,
+  response      TEXT,
+  created_at    TIMESTAMP NOT NULL
+);
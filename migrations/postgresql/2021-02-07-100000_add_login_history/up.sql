@@ -0,0 +1,15 @@
+CREATE TABLE login_history (
+  uuid            CHAR(36) NOT NULL PRIMARY KEY,
+  user_uuid       CHAR(36) NOT NULL REFERENCES users (uuid),
+  created_at      TIMESTAMP NOT NULL,
+  ip_address      TEXT     NOT NULL,
+  device_type     INTEGER,
+  device_name     TEXT,
+  grant_type      TEXT     NOT NULL,
+  twofactor_type  INTEGER,
+  success         BOOLEAN  NOT NULL,
+  failure_reason  TEXT
+);
+
+CREATE INDEX login_history_user_created_idx ON login_history (user_uuid, created_at);
+CREATE INDEX login_history_created_idx ON login_history (created_at);
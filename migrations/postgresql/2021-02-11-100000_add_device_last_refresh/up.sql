@@ -0,0 +1 @@
+ALTER TABLE devices ADD COLUMN last_refresh_at TIMESTAMP DEFAULT NULL;
@@ -0,0 +1,2 @@
+-- SQLite INTEGER columns already store 64-bit values, only the Diesel schema type needs to change
+SELECT 1;
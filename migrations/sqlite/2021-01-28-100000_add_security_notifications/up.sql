@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN security_notifications BOOLEAN NOT NULL DEFAULT 1;
+ALTER TABLE users ADD COLUMN failed_twofactor_attempts INTEGER NOT NULL DEFAULT 0;
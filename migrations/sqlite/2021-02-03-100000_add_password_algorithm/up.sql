@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN password_algorithm INTEGER NOT NULL DEFAULT 0;
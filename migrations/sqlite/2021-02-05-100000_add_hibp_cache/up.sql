@@ -0,0 +1,5 @@
+CREATE TABLE hibp_cache (
+  account_hash  TEXT     NOT NULL PRIMARY KEY,
+  response      TEXT,
+  created_at    DATETIME NOT NULL
+);
@@ -0,0 +1,2 @@
+ALTER TABLE devices ADD COLUMN previous_refresh_token TEXT DEFAULT NULL;
+ALTER TABLE devices ADD COLUMN session_created_at DATETIME DEFAULT NULL;
@@ -13,7 +13,7 @@ use rocket::{
 use rocket_contrib::json::Json;
 
 use crate::{
-    api::{ApiResult, EmptyResult, JsonResult, NumberOrString},
+    api::{icon_cache_stats, ApiResult, EmptyResult, JsonResult, NumberOrString},
     auth::{decode_admin, encode_jwt, generate_admin_claims, ClientIp},
     config::ConfigBuilder,
     db::{backup_database, models::*, DbConn, DbConnType},
@@ -31,12 +31,14 @@ pub fn routes() -> Vec<Route> {
     routes![
         admin_login,
         get_users_json,
+        get_user_login_history,
         post_admin_login,
         admin_page,
         invite_user,
         logout,
         delete_user,
         deauth_user,
+        revoke_user_device,
         disable_user,
         enable_user,
         remove_2fa,
@@ -46,11 +48,14 @@ pub fn routes() -> Vec<Route> {
         delete_config,
         backup_db,
         test_smtp,
+        mail_preview,
         users_overview,
         organizations_overview,
         delete_organization,
         diagnostics,
-        get_diagnostics_config
+        get_diagnostics_config,
+        resend_mail,
+        delete_mail
     ]
 }
 
@@ -180,6 +185,7 @@ struct AdminTemplateData {
     users: Option<Vec<Value>>,
     organizations: Option<Vec<Value>>,
     diagnostics: Option<Value>,
+    mail_templates: Option<&'static [&'static str]>,
     config: Value,
     can_backup: bool,
     logged_in: bool,
@@ -198,6 +204,7 @@ impl AdminTemplateData {
             users: None,
             organizations: None,
             diagnostics: None,
+            mail_templates: Some(mail::PREVIEW_TEMPLATES),
         }
     }
 
@@ -212,6 +219,7 @@ impl AdminTemplateData {
             urlpath: CONFIG.domain_path(),
             organizations: None,
             diagnostics: None,
+            mail_templates: None,
         }
     }
 
@@ -226,6 +234,7 @@ impl AdminTemplateData {
             urlpath: CONFIG.domain_path(),
             users: None,
             diagnostics: None,
+            mail_templates: None,
         }
     }
 
@@ -240,6 +249,7 @@ impl AdminTemplateData {
             urlpath: CONFIG.domain_path(),
             users: None,
             diagnostics: Some(diagnostics),
+            mail_templates: None,
         }
     }
 
@@ -272,7 +282,7 @@ fn invite_user(data: Json<InviteData>, _token: AdminToken, conn: DbConn) -> Empt
     user.save(&conn)?;
 
     if CONFIG.mail_enabled() {
-        mail::send_invite(&user.email, &user.uuid, None, None, &CONFIG.invitation_org_name(), None)
+        mail::send_invite(&user.email, &user.uuid, None, None, &CONFIG.invitation_org_name(), None, &user.culture)
     } else {
         let invitation = Invitation::new(data.email);
         invitation.save(&conn)
@@ -290,6 +300,19 @@ fn test_smtp(data: Json<InviteData>, _token: AdminToken) -> EmptyResult {
     }
 }
 
+#[get("/mail_preview/<template>")]
+fn mail_preview(template: String, _token: AdminToken) -> ApiResult<Html<String>> {
+    use handlebars::html_escape;
+
+    let (subject, body_html, body_text) = mail::preview_email(&template)?;
+    Ok(Html(format!(
+        "<h3>Subject: {}</h3><hr>{}<hr><pre>{}</pre>",
+        html_escape(&subject),
+        body_html,
+        html_escape(&body_text)
+    )))
+}
+
 #[get("/logout")]
 fn logout(mut cookies: Cookies, referer: Referer) -> Result<Redirect, ()> {
     cookies.remove(Cookie::named(COOKIE_NAME));
@@ -304,6 +327,15 @@ fn get_users_json(_token: AdminToken, conn: DbConn) -> JsonResult {
     Ok(Json(Value::Array(users_json)))
 }
 
+#[get("/users/<uuid>/login-history")]
+fn get_user_login_history(uuid: String, _token: AdminToken, conn: DbConn) -> JsonResult {
+    let user = User::find_by_uuid(&uuid, &conn).map_res("User doesn't exist")?;
+    let history = LoginHistory::find_by_user(&user.uuid, &conn);
+    let history_json: Vec<Value> = history.iter().map(LoginHistory::to_json).collect();
+
+    Ok(Json(Value::Array(history_json)))
+}
+
 #[get("/users/overview")]
 fn users_overview(_token: AdminToken, conn: DbConn) -> ApiResult<Html<String>> {
     let users = User::get_all(&conn);
@@ -313,13 +345,23 @@ fn users_overview(_token: AdminToken, conn: DbConn) -> ApiResult<Html<String>> {
             let mut usr = u.to_json(&conn);
             usr["cipher_count"] = json!(Cipher::count_owned_by_user(&u.uuid, &conn));
             usr["attachment_count"] = json!(Attachment::count_by_user(&u.uuid, &conn));
-            usr["attachment_size"] = json!(get_display_size(Attachment::size_by_user(&u.uuid, &conn) as i32));
+            usr["attachment_size"] = json!(get_display_size(Attachment::size_by_user(&u.uuid, &conn)));
             usr["user_enabled"] = json!(u.enabled);
             usr["created_at"] = json!(format_naive_datetime_local(&u.created_at, dt_fmt));
             usr["last_active"] = match u.last_active(&conn) {
                 Some(dt) => json!(format_naive_datetime_local(&dt, dt_fmt)),
                 None => json!("Never")
             };
+            usr["devices"] = Device::find_by_user(&u.uuid, &conn)
+                .iter()
+                .filter(|d| !d.is_revoked())
+                .map(|d| json!({
+                    "Id": d.uuid,
+                    "Name": d.name,
+                    "TypeName": Device::type_name(d.atype),
+                    "last_used": format_naive_datetime_local(&d.updated_at, dt_fmt),
+                }))
+                .collect();
             usr
         })
         .collect();
@@ -331,7 +373,18 @@ fn users_overview(_token: AdminToken, conn: DbConn) -> ApiResult<Html<String>> {
 #[post("/users/<uuid>/delete")]
 fn delete_user(uuid: String, _token: AdminToken, conn: DbConn) -> EmptyResult {
     let user = User::find_by_uuid(&uuid, &conn).map_res("User doesn't exist")?;
-    user.delete(&conn)
+
+    // The attachment files are deleted once the transaction is committed
+    let attachments = Attachment::find_by_user(&user.uuid, &conn);
+    conn.transaction(|| {
+        for attachment in &attachments {
+            attachment.delete_row(&conn)?;
+        }
+        user.delete(&conn)
+    })?;
+
+    Attachment::delete_files(&attachments);
+    Ok(())
 }
 
 #[post("/users/<uuid>/deauth")]
@@ -340,7 +393,20 @@ fn deauth_user(uuid: String, _token: AdminToken, conn: DbConn) -> EmptyResult {
     Device::delete_all_by_user(&user.uuid, &conn)?;
     user.reset_security_stamp();
 
-    user.save(&conn)
+    user.save(&conn)?;
+    notify_admin_action(&user, "deauthorized");
+    Ok(())
+}
+
+#[post("/users/<uuid>/devices/<device_uuid>/revoke")]
+fn revoke_user_device(uuid: String, device_uuid: String, _token: AdminToken, conn: DbConn) -> EmptyResult {
+    let user = User::find_by_uuid(&uuid, &conn).map_res("User doesn't exist")?;
+    let mut device = Device::find_by_uuid_and_user(&device_uuid, &user.uuid, &conn).map_res("Device doesn't exist")?;
+    device.revoke();
+
+    device.save(&conn)?;
+    notify_admin_action(&user, "device_revoked");
+    Ok(())
 }
 
 #[post("/users/<uuid>/disable")]
@@ -350,7 +416,9 @@ fn disable_user(uuid: String, _token: AdminToken, conn: DbConn) -> EmptyResult {
     user.reset_security_stamp();
     user.enabled = false;
 
-    user.save(&conn)
+    user.save(&conn)?;
+    notify_admin_action(&user, "disabled");
+    Ok(())
 }
 
 #[post("/users/<uuid>/enable")]
@@ -366,7 +434,15 @@ fn remove_2fa(uuid: String, _token: AdminToken, conn: DbConn) -> EmptyResult {
     let mut user = User::find_by_uuid(&uuid, &conn).map_res("User doesn't exist")?;
     TwoFactor::delete_all_by_user(&user.uuid, &conn)?;
     user.totp_recover = None;
-    user.save(&conn)
+    user.save(&conn)?;
+    notify_admin_action(&user, "twofactor_removed");
+    Ok(())
+}
+
+fn notify_admin_action(user: &User, action: &str) {
+    if let Err(e) = mail::send_admin_action(user, action) {
+        error!("Error sending admin action notification: {:#?}", e);
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -418,7 +494,7 @@ fn organizations_overview(_token: AdminToken, conn: DbConn) -> ApiResult<Html<St
             org["user_count"] = json!(UserOrganization::count_by_org(&o.uuid, &conn));
             org["cipher_count"] = json!(Cipher::count_by_org(&o.uuid, &conn));
             org["attachment_count"] = json!(Attachment::count_by_org(&o.uuid, &conn));
-            org["attachment_size"] = json!(get_display_size(Attachment::size_by_org(&o.uuid, &conn) as i32));
+            org["attachment_size"] = json!(get_display_size(Attachment::size_by_org(&o.uuid, &conn)));
             org
         })
         .collect();
@@ -475,7 +551,7 @@ fn has_http_access() -> bool {
 }
 
 #[get("/diagnostics")]
-fn diagnostics(_token: AdminToken, _conn: DbConn) -> ApiResult<Html<String>> {
+fn diagnostics(_token: AdminToken, conn: DbConn) -> ApiResult<Html<String>> {
     use crate::util::read_file_string;
     use chrono::prelude::*;
     use std::net::ToSocketAddrs;
@@ -539,6 +615,9 @@ fn diagnostics(_token: AdminToken, _conn: DbConn) -> ApiResult<Html<String>> {
         "has_http_access": has_http_access,
         "uses_proxy": uses_proxy,
         "db_type": *DB_TYPE,
+        "mail_queue_pending": QueuedMail::count_pending(&conn),
+        "mail_queue_failed": QueuedMail::find_failed(&conn).iter().map(|m| m.to_json()).collect::<Vec<Value>>(),
+        "icon_cache": icon_cache_stats(),
         "admin_url": format!("{}/diagnostics", admin_url(Referer(None))),
         "server_time": Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string(), // Run the date/time check as the last item to minimize the difference
     });
@@ -547,6 +626,22 @@ fn diagnostics(_token: AdminToken, _conn: DbConn) -> ApiResult<Html<String>> {
     Ok(Html(text))
 }
 
+#[post("/mail_queue/<uuid>/resend")]
+fn resend_mail(uuid: String, _token: AdminToken, conn: DbConn) -> EmptyResult {
+    let mut mail = QueuedMail::find_by_uuid(&uuid, &conn).map_res("Queued mail doesn't exist")?;
+    mail.reset();
+    mail.save(&conn)?;
+
+    mail::notify_queue();
+    Ok(())
+}
+
+#[post("/mail_queue/<uuid>/delete")]
+fn delete_mail(uuid: String, _token: AdminToken, conn: DbConn) -> EmptyResult {
+    let mail = QueuedMail::find_by_uuid(&uuid, &conn).map_res("Queued mail doesn't exist")?;
+    mail.delete(&conn)
+}
+
 #[get("/diagnostics/config")]
 fn get_diagnostics_config(_token: AdminToken) -> JsonResult {
     let support_json = CONFIG.get_support_json();
@@ -1,12 +1,28 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
 use chrono::Utc;
+use once_cell::sync::Lazy;
+use ring::hmac;
+use rocket::response::content::Html;
 use rocket_contrib::json::Json;
+use serde_json::Value;
 
 use crate::{
-    api::{EmptyResult, JsonResult, JsonUpcase, Notify, NumberOrString, PasswordData, UpdateType},
-    auth::{decode_delete, decode_invite, decode_verify_email, Headers},
+    api::{ApiResult, EmptyResult, JsonResult, JsonUpcase, Notify, NumberOrString, PasswordData, UpdateType},
+    auth::{decode_delete, decode_invite, decode_notifications, decode_verify_email, ClientIp, Headers},
     crypto,
     db::{models::*, DbConn},
-    mail, CONFIG,
+    error::Error,
+    mail,
+    util::read_file,
+    CONFIG,
 };
 
 pub fn routes() -> Vec<rocket::Route> {
@@ -30,18 +46,24 @@ pub fn routes() -> Vec<rocket::Route> {
         delete_account,
         post_delete_account,
         revision_date,
+        login_history,
         password_hint,
         prelogin,
         verify_password,
+        security_notifications,
+        post_security_notifications,
     ]
 }
 
 #[derive(Deserialize, Debug)]
 #[allow(non_snake_case)]
 struct RegisterData {
+    Culture: Option<String>,
     Email: String,
     Kdf: Option<i32>,
     KdfIterations: Option<i32>,
+    KdfMemory: Option<i32>,
+    KdfParallelism: Option<i32>,
     Key: String,
     Keys: Option<KeysData>,
     MasterPasswordHash: String,
@@ -59,17 +81,24 @@ struct KeysData {
 }
 
 #[post("/accounts/register", data = "<data>")]
-fn register(data: JsonUpcase<RegisterData>, conn: DbConn) -> EmptyResult {
-    let data: RegisterData = data.into_inner().data;
+fn register(data: JsonUpcase<RegisterData>, ip: ClientIp, conn: DbConn) -> EmptyResult {
+    check_account_lookup_rate_limit(&ip.ip)?;
+    _register(data.into_inner().data, &conn)
+}
 
+fn _register(data: RegisterData, conn: &DbConn) -> EmptyResult {
     let mut user = match User::find_by_mail(&data.Email, &conn) {
         Some(user) => {
             if !user.password_hash.is_empty() {
-                if CONFIG.is_signup_allowed(&data.Email) {
-                    err!("User already exists")
-                } else {
-                    err!("Registration not allowed or user already exists")
+                // Let the owner know, otherwise they wouldn't find out why they can't register
+                if CONFIG.mail_enabled() {
+                    if let Err(e) = mail::send_account_exists(&user.email, &user.culture) {
+                        error!("Error sending account exists email: {:#?}", e);
+                    }
                 }
+                // Take as long as a new account, which gets its password hashed
+                User::hash_dummy_password(&data.MasterPasswordHash);
+                return existing_account_registration(&data.Email);
             }
 
             if let Some(token) = data.Token {
@@ -86,10 +115,15 @@ fn register(data: JsonUpcase<RegisterData>, conn: DbConn) -> EmptyResult {
                 }
 
                 user
-            } else if CONFIG.is_signup_allowed(&data.Email) {
-                err!("Account with this email already exists")
             } else {
-                err!("Registration not allowed or user already exists")
+                // Send the invitation again, otherwise they wouldn't find out how to finish the registration
+                if CONFIG.mail_enabled() {
+                    if let Err(e) = resend_invite(&user, &conn) {
+                        error!("Error sending invite email: {:#?}", e);
+                    }
+                }
+                User::hash_dummy_password(&data.MasterPasswordHash);
+                return existing_account_registration(&data.Email);
             }
         }
         None => {
@@ -99,6 +133,7 @@ fn register(data: JsonUpcase<RegisterData>, conn: DbConn) -> EmptyResult {
             if Invitation::take(&data.Email, &conn) || CONFIG.is_signup_allowed(&data.Email) {
                 User::new(data.Email.clone())
             } else {
+                User::hash_dummy_password(&data.MasterPasswordHash);
                 err!("Registration not allowed or user already exists")
             }
         }
@@ -107,12 +142,14 @@ fn register(data: JsonUpcase<RegisterData>, conn: DbConn) -> EmptyResult {
     // Make sure we don't leave a lingering invitation.
     Invitation::take(&data.Email, &conn);
 
-    if let Some(client_kdf_iter) = data.KdfIterations {
-        user.client_kdf_iter = client_kdf_iter;
-    }
-
-    if let Some(client_kdf_type) = data.Kdf {
-        user.client_kdf_type = client_kdf_type;
+    if data.Kdf.is_some() || data.KdfIterations.is_some() {
+        set_client_kdf(
+            &mut user,
+            data.Kdf.unwrap_or(User::CLIENT_KDF_TYPE_DEFAULT),
+            data.KdfIterations.unwrap_or(User::CLIENT_KDF_ITER_DEFAULT),
+            data.KdfMemory,
+            data.KdfParallelism,
+        )?;
     }
 
     user.set_password(&data.MasterPasswordHash, None);
@@ -127,6 +164,10 @@ fn register(data: JsonUpcase<RegisterData>, conn: DbConn) -> EmptyResult {
         user.password_hint = Some(hint);
     }
 
+    if let Some(culture) = data.Culture {
+        user.set_culture(&culture);
+    }
+
     if let Some(keys) = data.Keys {
         user.private_key = Some(keys.EncryptedPrivateKey);
         user.public_key = Some(keys.PublicKey);
@@ -134,13 +175,13 @@ fn register(data: JsonUpcase<RegisterData>, conn: DbConn) -> EmptyResult {
 
     if CONFIG.mail_enabled() {
         if CONFIG.signups_verify() {
-            if let Err(e) = mail::send_welcome_must_verify(&user.email, &user.uuid) {
+            if let Err(e) = mail::send_welcome_must_verify(&user.email, &user.uuid, &user.culture) {
                 error!("Error sending welcome email: {:#?}", e);
             }
 
             user.last_verifying_at = Some(user.created_at);
         } else {
-            if let Err(e) = mail::send_welcome(&user.email) {
+            if let Err(e) = mail::send_welcome(&user.email, &user.culture) {
                 error!("Error sending welcome email: {:#?}", e);
             }
         }
@@ -149,6 +190,62 @@ fn register(data: JsonUpcase<RegisterData>, conn: DbConn) -> EmptyResult {
     user.save(&conn)
 }
 
+/// Sends the invitation to an invited user again, for the first organization they are still invited to
+fn resend_invite(user: &User, conn: &DbConn) -> EmptyResult {
+    let invited = UserOrganization::find_invited_by_user(&user.uuid, conn).into_iter().find_map(|user_org| {
+        Organization::find_by_uuid(&user_org.org_uuid, conn).map(|org| (user_org, org))
+    });
+
+    match invited {
+        Some((user_org, org)) => mail::send_invite(
+            &user.email,
+            &user.uuid,
+            Some(org.uuid),
+            Some(user_org.uuid),
+            &org.name,
+            None,
+            &user.culture,
+        ),
+        None => mail::send_invite(&user.email, &user.uuid, None, None, &CONFIG.invitation_org_name(), None, &user.culture),
+    }
+}
+
+/// Responds to the registration of an email that already has an account the same way as for an unknown email,
+/// so the registration can't be used to find which accounts exist
+fn existing_account_registration(email: &str) -> EmptyResult {
+    info!("Registration attempt for an existing account: {}", email);
+
+    if CONFIG.is_signup_allowed(email) {
+        Ok(())
+    } else {
+        err!("Registration not allowed or user already exists")
+    }
+}
+
+// Registrations and password hint requests made from each IP address in the current period. Both do the same work for
+// the existing and unknown emails, but any remaining difference in the responses would need lots of requests to notice
+static ACCOUNT_LOOKUPS: Lazy<Mutex<HashMap<IpAddr, (Instant, u32)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+const ACCOUNT_LOOKUP_RATE_LIMIT_PERIOD: Duration = Duration::from_secs(3600);
+
+fn check_account_lookup_rate_limit(ip: &IpAddr) -> EmptyResult {
+    let limit = CONFIG.account_lookup_rate_limit();
+    if limit == 0 {
+        return Ok(());
+    }
+
+    let now = Instant::now();
+    let mut lookups = ACCOUNT_LOOKUPS.lock().unwrap();
+    lookups.retain(|_, (start, _)| now.duration_since(*start) < ACCOUNT_LOOKUP_RATE_LIMIT_PERIOD);
+
+    let (_, count) = lookups.entry(*ip).or_insert((now, 0));
+    if *count >= limit {
+        return Err(Error::new("Too many requests, try again later", format!("IP: {}", ip)).with_code(429));
+    }
+
+    *count += 1;
+    Ok(())
+}
+
 #[get("/accounts/profile")]
 fn profile(headers: Headers, conn: DbConn) -> JsonResult {
     Ok(Json(headers.user.to_json(&conn)))
@@ -157,8 +254,7 @@ fn profile(headers: Headers, conn: DbConn) -> JsonResult {
 #[derive(Deserialize, Debug)]
 #[allow(non_snake_case)]
 struct ProfileData {
-    #[serde(rename = "Culture")]
-    _Culture: String, // Ignored, always use en-US
+    Culture: Option<String>,
     MasterPasswordHint: Option<String>,
     Name: String,
 }
@@ -179,6 +275,9 @@ fn post_profile(data: JsonUpcase<ProfileData>, headers: Headers, conn: DbConn) -
         Some(ref h) if h.is_empty() => None,
         _ => data.MasterPasswordHint,
     };
+    if let Some(culture) = data.Culture {
+        user.set_culture(&culture);
+    }
     user.save(&conn)?;
     Ok(Json(user.to_json(&conn)))
 }
@@ -234,7 +333,13 @@ fn post_password(data: JsonUpcase<ChangePassData>, headers: Headers, conn: DbCon
 
     user.set_password(&data.NewMasterPasswordHash, Some("post_rotatekey"));
     user.akey = data.Key;
-    user.save(&conn)
+    user.save(&conn)?;
+
+    if let Err(e) = mail::send_password_changed(&user) {
+        error!("Error sending password change notification: {:#?}", e);
+    }
+
+    Ok(())
 }
 
 #[derive(Deserialize)]
@@ -242,6 +347,8 @@ fn post_password(data: JsonUpcase<ChangePassData>, headers: Headers, conn: DbCon
 struct ChangeKdfData {
     Kdf: i32,
     KdfIterations: i32,
+    KdfMemory: Option<i32>,
+    KdfParallelism: Option<i32>,
 
     MasterPasswordHash: String,
     NewMasterPasswordHash: String,
@@ -257,11 +364,73 @@ fn post_kdf(data: JsonUpcase<ChangeKdfData>, headers: Headers, conn: DbConn) ->
         err!("Invalid password")
     }
 
-    user.client_kdf_iter = data.KdfIterations;
-    user.client_kdf_type = data.Kdf;
+    set_client_kdf(&mut user, data.Kdf, data.KdfIterations, data.KdfMemory, data.KdfParallelism)?;
     user.set_password(&data.NewMasterPasswordHash, None);
     user.akey = data.Key;
-    user.save(&conn)
+    user.save(&conn)?;
+
+    if let Err(e) = mail::send_kdf_changed(&user) {
+        error!("Error sending KDF change notification: {:#?}", e);
+    }
+
+    Ok(())
+}
+
+/// Checks that the client KDF settings are supported and strong enough, and stores them in the user
+fn set_client_kdf(
+    user: &mut User,
+    kdf: i32,
+    iterations: i32,
+    memory: Option<i32>,
+    parallelism: Option<i32>,
+) -> EmptyResult {
+    use num_traits::FromPrimitive;
+
+    match UserKdfType::from_i32(kdf) {
+        Some(UserKdfType::Pbkdf2) => {
+            if iterations < User::CLIENT_KDF_PBKDF2_ITER_MIN {
+                err!(format!("PBKDF2 KDF iterations must be at least {}", User::CLIENT_KDF_PBKDF2_ITER_MIN))
+            }
+            user.client_kdf_memory = None;
+            user.client_kdf_parallelism = None;
+        }
+        Some(UserKdfType::Argon2id) => {
+            if iterations < User::CLIENT_KDF_ARGON2_ITER_MIN {
+                err!(format!("Argon2 KDF iterations must be at least {}", User::CLIENT_KDF_ARGON2_ITER_MIN))
+            }
+
+            let memory = match memory {
+                Some(m) if (User::CLIENT_KDF_ARGON2_MEMORY_MIN..=User::CLIENT_KDF_ARGON2_MEMORY_MAX).contains(&m) => m,
+                _ => err!(format!(
+                    "Argon2 memory must be between {} MiB and {} MiB",
+                    User::CLIENT_KDF_ARGON2_MEMORY_MIN,
+                    User::CLIENT_KDF_ARGON2_MEMORY_MAX
+                )),
+            };
+
+            let parallelism = match parallelism {
+                Some(p)
+                    if (User::CLIENT_KDF_ARGON2_PARALLELISM_MIN..=User::CLIENT_KDF_ARGON2_PARALLELISM_MAX)
+                        .contains(&p) =>
+                {
+                    p
+                }
+                _ => err!(format!(
+                    "Argon2 parallelism must be between {} and {}",
+                    User::CLIENT_KDF_ARGON2_PARALLELISM_MIN,
+                    User::CLIENT_KDF_ARGON2_PARALLELISM_MAX
+                )),
+            };
+
+            user.client_kdf_memory = Some(memory);
+            user.client_kdf_parallelism = Some(parallelism);
+        }
+        None => err!("Unsupported KDF type"),
+    }
+
+    user.client_kdf_type = kdf;
+    user.client_kdf_iter = iterations;
+    Ok(())
 }
 
 #[derive(Deserialize)]
@@ -293,54 +462,87 @@ fn post_rotatekey(data: JsonUpcase<KeyData>, headers: Headers, conn: DbConn, nt:
 
     let user_uuid = &headers.user.uuid;
 
-    // Update folder data
-    for folder_data in data.Folders {
-        let mut saved_folder = match Folder::find_by_uuid(&folder_data.Id, &conn) {
-            Some(folder) => folder,
-            None => err!("Folder doesn't exist"),
-        };
+    // All the personal items need to be re-encrypted, otherwise the missing ones couldn't be decrypted anymore
+    let mut folder_ids: Vec<&str> = data.Folders.iter().map(|f| f.Id.as_str()).collect();
+    let mut user_folder_ids: Vec<String> = Folder::find_by_user(user_uuid, &conn).into_iter().map(|f| f.uuid).collect();
+    folder_ids.sort_unstable();
+    user_folder_ids.sort_unstable();
+    if folder_ids != user_folder_ids {
+        err!("All the folders of the user must be included in the key rotation")
+    }
+
+    let mut cipher_ids: Vec<&str> = data.Ciphers.iter().filter_map(|c| c.Id.as_deref()).collect();
+    let mut user_cipher_ids: Vec<String> =
+        Cipher::find_owned_by_user(user_uuid, &conn).into_iter().map(|c| c.uuid).collect();
+    cipher_ids.sort_unstable();
+    user_cipher_ids.sort_unstable();
+    if cipher_ids.len() != data.Ciphers.len() || cipher_ids != user_cipher_ids {
+        err!("All the ciphers of the user must be included in the key rotation")
+    }
+
+    // Everything is saved in a single transaction, a partial rotation would leave the vault unusable
+    let user = conn.transaction(|| {
+        let user_uuid = &headers.user.uuid;
 
-        if &saved_folder.user_uuid != user_uuid {
-            err!("The folder is not owned by the user")
+        // Update folder data
+        for folder_data in data.Folders {
+            let mut saved_folder = match Folder::find_by_uuid(&folder_data.Id, &conn) {
+                Some(folder) => folder,
+                None => err!("Folder doesn't exist"),
+            };
+
+            if &saved_folder.user_uuid != user_uuid {
+                err!("The folder is not owned by the user")
+            }
+
+            saved_folder.name = folder_data.Name;
+            saved_folder.save(&conn)?
         }
 
-        saved_folder.name = folder_data.Name;
-        saved_folder.save(&conn)?
-    }
+        // Update cipher data
+        use super::ciphers::update_cipher_from_data;
 
-    // Update cipher data
-    use super::ciphers::update_cipher_from_data;
+        for cipher_data in data.Ciphers {
+            let mut saved_cipher = match Cipher::find_by_uuid(cipher_data.Id.as_ref().unwrap(), &conn) {
+                Some(cipher) => cipher,
+                None => err!("Cipher doesn't exist"),
+            };
 
-    for cipher_data in data.Ciphers {
-        let mut saved_cipher = match Cipher::find_by_uuid(cipher_data.Id.as_ref().unwrap(), &conn) {
-            Some(cipher) => cipher,
-            None => err!("Cipher doesn't exist"),
-        };
+            if saved_cipher.user_uuid.as_ref().unwrap() != user_uuid {
+                err!("The cipher is not owned by the user")
+            }
 
-        if saved_cipher.user_uuid.as_ref().unwrap() != user_uuid {
-            err!("The cipher is not owned by the user")
+            update_cipher_from_data(
+                &mut saved_cipher,
+                cipher_data,
+                &headers,
+                false,
+                &conn,
+                &nt,
+                UpdateType::None,
+            )?
         }
 
-        update_cipher_from_data(
-            &mut saved_cipher,
-            cipher_data,
-            &headers,
-            false,
-            &conn,
-            &nt,
-            UpdateType::CipherUpdate,
-        )?
-    }
+        // Update user data
+        let mut user = headers.user;
 
-    // Update user data
-    let mut user = headers.user;
+        user.akey = data.Key;
+        user.private_key = Some(data.PrivateKey);
+        user.reset_security_stamp();
+        user.reset_stamp_exception();
 
-    user.akey = data.Key;
-    user.private_key = Some(data.PrivateKey);
-    user.reset_security_stamp();
-    user.reset_stamp_exception();
+        user.save(&conn)?;
+        Ok(user)
+    })?;
 
-    user.save(&conn)
+    // A single notification once everything is saved, instead of one for each cipher
+    nt.send_user_update(UpdateType::Vault, &user);
+
+    if let Err(e) = mail::send_key_rotated(&user) {
+        error!("Error sending key rotation notification: {:#?}", e);
+    }
+
+    Ok(())
 }
 
 #[post("/accounts/security-stamp", data = "<data>")]
@@ -384,7 +586,7 @@ fn post_email_token(data: JsonUpcase<EmailTokenData>, headers: Headers, conn: Db
     let token = crypto::generate_token(6)?;
 
     if CONFIG.mail_enabled() {
-        if let Err(e) = mail::send_change_email(&data.NewEmail, &token) {
+        if let Err(e) = mail::send_change_email(&data.NewEmail, &token, &user.culture) {
             error!("Error sending change-email email: {:#?}", e);
         }
     }
@@ -442,14 +644,20 @@ fn post_email(data: JsonUpcase<ChangeEmailData>, headers: Headers, conn: DbConn)
         user.verified_at = None;
     }
 
-    user.email = data.NewEmail;
+    let old_email = std::mem::replace(&mut user.email, data.NewEmail);
     user.email_new = None;
     user.email_new_token = None;
 
     user.set_password(&data.NewMasterPasswordHash, None);
     user.akey = data.Key;
 
-    user.save(&conn)
+    user.save(&conn)?;
+
+    if let Err(e) = mail::send_email_changed(&old_email, &user) {
+        error!("Error sending email change notification: {:#?}", e);
+    }
+
+    Ok(())
 }
 
 #[post("/accounts/verify-email")]
@@ -460,7 +668,7 @@ fn post_verify_email(headers: Headers, _conn: DbConn) -> EmptyResult {
         err!("Cannot verify email address");
     }
 
-    if let Err(e) = mail::send_verify_email(&user.email, &user.uuid) {
+    if let Err(e) = mail::send_verify_email(&user.email, &user.uuid, &user.culture) {
         error!("Error sending verify_email email: {:#?}", e);
     }
 
@@ -514,7 +722,7 @@ fn post_delete_recover(data: JsonUpcase<DeleteRecoverData>, conn: DbConn) -> Emp
 
     if CONFIG.mail_enabled() {
         if let Some(user) = user {
-            if let Err(e) = mail::send_delete_account(&user.email, &user.uuid) {
+            if let Err(e) = mail::send_delete_account(&user.email, &user.uuid, &user.culture) {
                 error!("Error sending delete account email: {:#?}", e);
             }
         }
@@ -577,6 +785,18 @@ fn revision_date(headers: Headers) -> String {
     revision_date.to_string()
 }
 
+#[get("/accounts/login-history")]
+fn login_history(headers: Headers, conn: DbConn) -> JsonResult {
+    let history = LoginHistory::find_by_user(&headers.user.uuid, &conn);
+    let history_json: Vec<Value> = history.iter().map(LoginHistory::to_json).collect();
+
+    Ok(Json(json!({
+        "Data": history_json,
+        "Object": "list",
+        "ContinuationToken": null,
+    })))
+}
+
 #[derive(Deserialize)]
 #[allow(non_snake_case)]
 struct PasswordHintData {
@@ -584,21 +804,29 @@ struct PasswordHintData {
 }
 
 #[post("/accounts/password-hint", data = "<data>")]
-fn password_hint(data: JsonUpcase<PasswordHintData>, conn: DbConn) -> EmptyResult {
+fn password_hint(data: JsonUpcase<PasswordHintData>, ip: ClientIp, conn: DbConn) -> EmptyResult {
+    check_account_lookup_rate_limit(&ip.ip)?;
     let data: PasswordHintData = data.into_inner().data;
+    _password_hint(&data.Email, &conn)
+}
+
+fn _password_hint(email: &str, conn: &DbConn) -> EmptyResult {
+    const NO_HINT: &str = "Sorry, you have no password hint...";
 
-    let hint = match User::find_by_mail(&data.Email, &conn) {
-        Some(user) => user.password_hint,
+    // The unknown emails get the same response as the accounts without a hint
+    let (hint, culture) = match User::find_by_mail(email, &conn) {
+        Some(user) => (user.password_hint, user.culture),
+        None if !CONFIG.mail_enabled() && CONFIG.show_password_hint() => err!(NO_HINT),
         None => return Ok(()),
     };
 
     if CONFIG.mail_enabled() {
-        mail::send_password_hint(&data.Email, hint)?;
+        mail::send_password_hint(email, hint, &culture)?;
     } else if CONFIG.show_password_hint() {
         if let Some(hint) = hint {
             err!(format!("Your password hint is: {}", &hint));
         } else {
-            err!("Sorry, you have no password hint...");
+            err!(NO_HINT);
         }
     }
 
@@ -615,16 +843,61 @@ struct PreloginData {
 fn prelogin(data: JsonUpcase<PreloginData>, conn: DbConn) -> JsonResult {
     let data: PreloginData = data.into_inner().data;
 
-    let (kdf_type, kdf_iter) = match User::find_by_mail(&data.Email, &conn) {
-        Some(user) => (user.client_kdf_type, user.client_kdf_iter),
-        None => (User::CLIENT_KDF_TYPE_DEFAULT, User::CLIENT_KDF_ITER_DEFAULT),
+    let (kdf_type, kdf_iter, kdf_mem, kdf_para) = match User::find_by_mail(&data.Email, &conn) {
+        Some(user) => (
+            user.client_kdf_type,
+            user.client_kdf_iter,
+            user.client_kdf_memory,
+            user.client_kdf_parallelism,
+        ),
+        None => fake_kdf_params(&data.Email),
     };
 
     Ok(Json(json!({
         "Kdf": kdf_type,
-        "KdfIterations": kdf_iter
+        "KdfIterations": kdf_iter,
+        "KdfMemory": kdf_mem,
+        "KdfParallelism": kdf_para,
     })))
 }
+// Secret used to generate the KDF settings returned for the unknown emails. It's stored so they don't change
+// when the server restarts, which would reveal that the accounts don't exist.
+static ENUMERATION_KEY: Lazy<hmac::Key> = Lazy::new(|| {
+    let path = CONFIG.enumeration_key_filename();
+    let secret = match read_file(&path) {
+        Ok(secret) if secret.len() == 32 => secret,
+        _ => {
+            let secret = crypto::get_random(vec![0u8; 32]);
+            if let Err(e) = File::create(&path).and_then(|mut f| f.write_all(&secret)) {
+                error!("Error saving the account enumeration key: {:?}", e);
+            }
+            secret
+        }
+    };
+    hmac::Key::new(hmac::HMAC_SHA256, &secret)
+});
+
+// Settings returned for the unknown emails, most of them get the default ones like most of the real accounts
+const FAKE_KDF_PARAMS: &[(i32, i32, Option<i32>, Option<i32>)] = &[
+    (UserKdfType::Pbkdf2 as i32, 200_000, None, None),
+    (UserKdfType::Pbkdf2 as i32, 350_000, None, None),
+    (UserKdfType::Pbkdf2 as i32, 600_000, None, None),
+    (UserKdfType::Argon2id as i32, 3, Some(64), Some(4)),
+];
+
+/// Returns plausible KDF settings for an unknown email, which are always the same for each email
+fn fake_kdf_params(email: &str) -> (i32, i32, Option<i32>, Option<i32>) {
+    let tag = hmac::sign(&ENUMERATION_KEY, email.to_lowercase().as_bytes());
+    let value = tag.as_ref()[0] as usize;
+
+    // About 80% of the emails get the default settings
+    if value < 205 {
+        (User::CLIENT_KDF_TYPE_DEFAULT, User::CLIENT_KDF_ITER_DEFAULT, None, None)
+    } else {
+        FAKE_KDF_PARAMS[value % FAKE_KDF_PARAMS.len()]
+    }
+}
+
 #[derive(Deserialize)]
 #[allow(non_snake_case)]
 struct VerifyPasswordData {
@@ -642,3 +915,66 @@ fn verify_password(data: JsonUpcase<VerifyPasswordData>, headers: Headers, _conn
 
     Ok(())
 }
+
+/// Opened from the links in the security notification emails. The link only shows a confirmation page, as it could be
+/// opened by email scanners and link previews, the change is made when the user submits it
+#[get("/accounts/security-notifications/<action>?<token>")]
+fn security_notifications(action: String, token: String, conn: DbConn) -> ApiResult<Html<String>> {
+    let (user, enabled) = security_notifications_user(&action, &token, &conn)?;
+
+    let (message, button) = if enabled {
+        (format!("Do you want to receive the security notification emails of {} again?", user.email), "Enable")
+    } else {
+        (format!("Do you want to stop receiving the security notification emails of {}?", user.email), "Disable")
+    };
+    render_security_notifications(&message, Some((&action, button)), &token)
+}
+
+#[post("/accounts/security-notifications/<action>?<token>")]
+fn post_security_notifications(action: String, token: String, conn: DbConn) -> ApiResult<Html<String>> {
+    let (mut user, enabled) = security_notifications_user(&action, &token, &conn)?;
+
+    user.security_notifications = enabled;
+    user.save(&conn)?;
+
+    if enabled {
+        render_security_notifications("Security notification emails have been enabled again.", None, &token)
+    } else {
+        render_security_notifications(
+            "You will no longer receive security notification emails.",
+            Some(("subscribe", "Enable them again")),
+            &token,
+        )
+    }
+}
+
+fn security_notifications_user(action: &str, token: &str, conn: &DbConn) -> ApiResult<(User, bool)> {
+    let enabled = match action {
+        "subscribe" => true,
+        "unsubscribe" => false,
+        _ => err!("Invalid action"),
+    };
+
+    let claims = match decode_notifications(token) {
+        Ok(claims) => claims,
+        Err(_) => err!("This link has expired, use the one in a more recent email"),
+    };
+    match User::find_by_uuid(&claims.sub, conn) {
+        Some(user) => Ok((user, enabled)),
+        None => err!("User doesn't exist"),
+    }
+}
+
+fn render_security_notifications(message: &str, form: Option<(&str, &str)>, token: &str) -> ApiResult<Html<String>> {
+    let text = CONFIG.render_template(
+        "security_notifications",
+        &json!({
+            "urlpath": CONFIG.domain_path(),
+            "message": message,
+            "action": form.map(|(action, _)| action),
+            "button": form.map(|(_, button)| button),
+            "token": token,
+        }),
+    )?;
+    Ok(Html(text))
+}
@@ -10,10 +10,12 @@ use data_encoding::HEXLOWER;
 use multipart::server::{save::SavedData, Multipart, SaveResult};
 
 use crate::{
-    api::{self, EmptyResult, JsonResult, JsonUpcase, Notify, PasswordData, UpdateType},
-    auth::Headers,
+    api::{self, ApiResult, EmptyResult, JsonResult, JsonUpcase, Notify, PasswordData, UpdateType},
+    auth::{decode_file_upload, encode_jwt, generate_file_upload_claims, Headers},
     crypto,
     db::{models::*, DbConn},
+    storage,
+    util::{ConditionalHeaders, ConditionalJson},
     CONFIG,
 };
 
@@ -39,7 +41,12 @@ pub fn routes() -> Vec<Route> {
         post_ciphers_admin,
         post_ciphers_create,
         post_ciphers_import,
+        get_attachment,
         post_attachment,
+        post_attachment_v2,
+        renew_attachment_upload,
+        post_attachment_v2_data_token,
+        post_attachment_v2_data,
         post_attachment_admin,
         post_attachment_share,
         delete_attachment_post,
@@ -84,50 +91,89 @@ struct SyncData {
 }
 
 #[get("/sync?<data..>")]
-fn sync(data: Form<SyncData>, headers: Headers, conn: DbConn) -> JsonResult {
-    let user_json = headers.user.to_json(&conn);
-
-    let folders = Folder::find_by_user(&headers.user.uuid, &conn);
-    let folders_json: Vec<Value> = folders.iter().map(Folder::to_json).collect();
-
-    let collections = Collection::find_by_user_uuid(&headers.user.uuid, &conn);
-    let collections_json: Vec<Value> = collections.iter()
-        .map(|c| c.to_json_details(&headers.user.uuid, &conn))
-        .collect();
+fn sync(data: Form<SyncData>, headers: Headers, conn: DbConn, cond: ConditionalHeaders) -> ApiResult<ConditionalJson> {
+    let etag = sync_etag(&data, &headers, &conn);
+
+    ConditionalJson::new(&etag, &cond, || {
+        let user_json = headers.user.to_json(&conn);
+
+        let folders = Folder::find_by_user(&headers.user.uuid, &conn);
+        let folders_json: Vec<Value> = folders.iter().map(Folder::to_json).collect();
+
+        let collections = Collection::find_by_user_uuid(&headers.user.uuid, &conn);
+        let collections_json: Vec<Value> = collections.iter()
+            .map(|c| c.to_json_details(&headers.user.uuid, &conn))
+            .collect();
+
+        let policies = OrgPolicy::find_by_user(&headers.user.uuid, &conn);
+        let policies_json: Vec<Value> = policies.iter().map(OrgPolicy::to_json).collect();
+
+        let ciphers = Cipher::find_by_user_visible(&headers.user.uuid, &conn);
+        let cipher_sync_data = CipherSyncData::new(&headers.user.uuid, &ciphers, &conn);
+        let ciphers_json: Vec<Value> = ciphers
+            .iter()
+            .map(|c| c.to_json(&headers.host, &headers.user.uuid, Some(&cipher_sync_data), &conn))
+            .collect();
+
+        let domains_json = if data.exclude_domains {
+            Value::Null
+        } else {
+            api::core::_get_eq_domains(&headers, true)?.into_inner()
+        };
 
-    let policies = OrgPolicy::find_by_user(&headers.user.uuid, &conn);
-    let policies_json: Vec<Value> = policies.iter().map(OrgPolicy::to_json).collect();
+        Ok(json!({
+            "Profile": user_json,
+            "Folders": folders_json,
+            "Collections": collections_json,
+            "Policies": policies_json,
+            "Ciphers": ciphers_json,
+            "Domains": domains_json,
+            "Object": "sync"
+        }))
+    })
+}
+
+/// Generates the ETag of the sync response, without having to build it.
+/// Every change to the user's vault, folders, collections and memberships updates the user's revision date.
+/// Policies and two-factor providers don't, so they are included separately,
+/// together with everything else the response depends on.
+fn sync_etag(data: &SyncData, headers: &Headers, conn: &DbConn) -> String {
+    use ring::digest::{digest, SHA256};
+
+    let user = &headers.user;
+    let mut state = vec![
+        option_env!("BWRS_VERSION").unwrap_or_default().to_string(),
+        headers.host.clone(),
+        user.uuid.clone(),
+        user.updated_at.timestamp_nanos().to_string(),
+        user.security_stamp.clone(),
+        data.exclude_domains.to_string(),
+    ];
+
+    for user_org in UserOrganization::find_by_user(&user.uuid, conn) {
+        state.push(format!("{}:{}:{}:{}", user_org.org_uuid, user_org.atype, user_org.status, user_org.access_all));
+    }
 
-    let ciphers = Cipher::find_by_user_visible(&headers.user.uuid, &conn);
-    let ciphers_json: Vec<Value> = ciphers
-        .iter()
-        .map(|c| c.to_json(&headers.host, &headers.user.uuid, &conn))
-        .collect();
+    for policy in OrgPolicy::find_by_user(&user.uuid, conn) {
+        state.push(format!("{}:{}:{}:{}", policy.uuid, policy.atype, policy.enabled, policy.data));
+    }
 
-    let domains_json = if data.exclude_domains {
-        Value::Null
-    } else {
-        api::core::_get_eq_domains(headers, true).unwrap().into_inner()
-    };
+    // Used for `TwoFactorEnabled` in the profile
+    for twofactor in TwoFactor::find_by_user(&user.uuid, conn) {
+        state.push(format!("{}:{}:{}", twofactor.uuid, twofactor.atype, twofactor.enabled));
+    }
 
-    Ok(Json(json!({
-        "Profile": user_json,
-        "Folders": folders_json,
-        "Collections": collections_json,
-        "Policies": policies_json,
-        "Ciphers": ciphers_json,
-        "Domains": domains_json,
-        "Object": "sync"
-    })))
+    HEXLOWER.encode(digest(&SHA256, state.join("|").as_bytes()).as_ref())
 }
 
 #[get("/ciphers")]
 fn get_ciphers(headers: Headers, conn: DbConn) -> JsonResult {
     let ciphers = Cipher::find_by_user_visible(&headers.user.uuid, &conn);
+    let cipher_sync_data = CipherSyncData::new(&headers.user.uuid, &ciphers, &conn);
 
     let ciphers_json: Vec<Value> = ciphers
         .iter()
-        .map(|c| c.to_json(&headers.host, &headers.user.uuid, &conn))
+        .map(|c| c.to_json(&headers.host, &headers.user.uuid, Some(&cipher_sync_data), &conn))
         .collect();
 
     Ok(Json(json!({
@@ -148,7 +194,7 @@ fn get_cipher(uuid: String, headers: Headers, conn: DbConn) -> JsonResult {
         err!("Cipher is not owned by user")
     }
 
-    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, &conn)))
+    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, None, &conn)))
 }
 
 #[get("/ciphers/<uuid>/admin")]
@@ -261,7 +307,7 @@ fn post_ciphers(data: JsonUpcase<CipherData>, headers: Headers, conn: DbConn, nt
     let mut cipher = Cipher::new(data.Type, data.Name.clone());
     update_cipher_from_data(&mut cipher, data, &headers, false, &conn, &nt, UpdateType::CipherCreate)?;
 
-    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, &conn)))
+    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, None, &conn)))
 }
 
 /// Enforces the personal ownership policy on user-owned ciphers, if applicable.
@@ -457,33 +503,38 @@ struct RelationsData {
 fn post_ciphers_import(data: JsonUpcase<ImportData>, headers: Headers, conn: DbConn, nt: Notify) -> EmptyResult {
     let data: ImportData = data.into_inner().data;
 
-    // Read and create the folders
-    let mut folders: Vec<_> = Vec::new();
-    for folder in data.Folders.into_iter() {
-        let mut new_folder = Folder::new(headers.user.uuid.clone(), folder.Name);
-        new_folder.save(&conn)?;
+    // Either the whole import succeeds, or nothing is imported
+    let user = conn.transaction(|| {
+        // Read and create the folders
+        let mut folders: Vec<_> = Vec::new();
+        for folder in data.Folders.into_iter() {
+            let mut new_folder = Folder::new(headers.user.uuid.clone(), folder.Name);
+            new_folder.save(&conn)?;
 
-        folders.push(new_folder);
-    }
+            folders.push(new_folder);
+        }
 
-    // Read the relations between folders and ciphers
-    let mut relations_map = HashMap::new();
+        // Read the relations between folders and ciphers
+        let mut relations_map = HashMap::new();
 
-    for relation in data.FolderRelationships {
-        relations_map.insert(relation.Key, relation.Value);
-    }
+        for relation in data.FolderRelationships {
+            relations_map.insert(relation.Key, relation.Value);
+        }
 
-    // Read and create the ciphers
-    for (index, mut cipher_data) in data.Ciphers.into_iter().enumerate() {
-        let folder_uuid = relations_map.get(&index).map(|i| folders[*i].uuid.clone());
-        cipher_data.FolderId = folder_uuid;
+        // Read and create the ciphers
+        for (index, mut cipher_data) in data.Ciphers.into_iter().enumerate() {
+            let folder_uuid = relations_map.get(&index).map(|i| folders[*i].uuid.clone());
+            cipher_data.FolderId = folder_uuid;
 
-        let mut cipher = Cipher::new(cipher_data.Type, cipher_data.Name.clone());
-        update_cipher_from_data(&mut cipher, cipher_data, &headers, false, &conn, &nt, UpdateType::None)?;
-    }
+            let mut cipher = Cipher::new(cipher_data.Type, cipher_data.Name.clone());
+            update_cipher_from_data(&mut cipher, cipher_data, &headers, false, &conn, &nt, UpdateType::None)?;
+        }
+
+        let mut user = headers.user;
+        user.update_revision(&conn)?;
+        Ok(user)
+    })?;
 
-    let mut user = headers.user;
-    user.update_revision(&conn)?;
     nt.send_user_update(UpdateType::Vault, &user);
     Ok(())
 }
@@ -536,7 +587,7 @@ fn put_cipher(uuid: String, data: JsonUpcase<CipherData>, headers: Headers, conn
 
     update_cipher_from_data(&mut cipher, data, &headers, false, &conn, &nt, UpdateType::CipherUpdate)?;
 
-    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, &conn)))
+    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, None, &conn)))
 }
 
 #[derive(Deserialize)]
@@ -595,7 +646,7 @@ fn post_collections_admin(
 
     let posted_collections: HashSet<String> = data.CollectionIds.iter().cloned().collect();
     let current_collections: HashSet<String> = cipher
-        .get_collections(&headers.user.uuid, &conn)
+        .get_collections(&headers.user.uuid, None, &conn)
         .iter()
         .cloned()
         .collect();
@@ -715,68 +766,277 @@ fn share_cipher_by_uuid(
     conn: &DbConn,
     nt: &Notify,
 ) -> JsonResult {
-    let mut cipher = match Cipher::find_by_uuid(&uuid, &conn) {
-        Some(cipher) => {
-            if cipher.is_write_accessible_to_user(&headers.user.uuid, &conn) {
-                cipher
-            } else {
-                err!("Cipher is not write accessible")
+    // The collections and the cipher are updated together, the clients are notified once they are saved
+    let (cipher, user_uuids) = conn.transaction(|| {
+        let mut cipher = match Cipher::find_by_uuid(&uuid, &conn) {
+            Some(cipher) => {
+                if cipher.is_write_accessible_to_user(&headers.user.uuid, &conn) {
+                    cipher
+                } else {
+                    err!("Cipher is not write accessible")
+                }
             }
-        }
-        None => err!("Cipher doesn't exist"),
-    };
+            None => err!("Cipher doesn't exist"),
+        };
 
-    let mut shared_to_collection = false;
-
-    match data.Cipher.OrganizationId.clone() {
-        // If we don't get an organization ID, we don't do anything
-        // No error because this is used when using the Clone functionality
-        None => {}
-        Some(organization_uuid) => {
-            for uuid in &data.CollectionIds {
-                match Collection::find_by_uuid_and_org(uuid, &organization_uuid, &conn) {
-                    None => err!("Invalid collection ID provided"),
-                    Some(collection) => {
-                        if collection.is_writable_by_user(&headers.user.uuid, &conn) {
-                            CollectionCipher::save(&cipher.uuid, &collection.uuid, &conn)?;
-                            shared_to_collection = true;
-                        } else {
-                            err!("No rights to modify the collection")
+        let mut shared_to_collection = false;
+
+        match data.Cipher.OrganizationId.clone() {
+            // If we don't get an organization ID, we don't do anything
+            // No error because this is used when using the Clone functionality
+            None => {}
+            Some(organization_uuid) => {
+                for uuid in &data.CollectionIds {
+                    match Collection::find_by_uuid_and_org(uuid, &organization_uuid, &conn) {
+                        None => err!("Invalid collection ID provided"),
+                        Some(collection) => {
+                            if collection.is_writable_by_user(&headers.user.uuid, &conn) {
+                                CollectionCipher::save(&cipher.uuid, &collection.uuid, &conn)?;
+                                shared_to_collection = true;
+                            } else {
+                                err!("No rights to modify the collection")
+                            }
                         }
                     }
                 }
             }
+        };
+
+        update_cipher_from_data(
+            &mut cipher,
+            data.Cipher,
+            &headers,
+            shared_to_collection,
+            &conn,
+            &nt,
+            UpdateType::None,
+        )?;
+
+        let user_uuids = cipher.update_users_revision(&conn);
+        Ok((cipher, user_uuids))
+    })?;
+
+    nt.send_cipher_update(UpdateType::CipherUpdate, &cipher, &user_uuids);
+    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, None, &conn)))
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct AttachmentRequestData {
+    Key: String,
+    FileName: String,
+    FileSize: i64,
+    AdminRequest: Option<bool>,
+}
+
+enum FileUploadType {
+    Direct = 0,
+    // Azure = 1, // only used upstream
+}
+
+/// Returns the number of bytes that can still be stored for the owner of the cipher, `None` if there is no limit
+fn attachment_size_left(cipher: &Cipher, conn: &DbConn) -> Result<Option<i64>, &'static str> {
+    let (limit_kb, used) = if let Some(ref user_uuid) = cipher.user_uuid {
+        (CONFIG.user_attachment_limit(), Attachment::size_by_user(user_uuid, conn))
+    } else if let Some(ref org_uuid) = cipher.organization_uuid {
+        (CONFIG.org_attachment_limit(), Attachment::size_by_org(org_uuid, conn))
+    } else {
+        return Err("Cipher is neither owned by a user nor an organization");
+    };
+
+    match limit_kb {
+        Some(0) => Err("Attachments are disabled"),
+        Some(limit_kb) => {
+            let left = (limit_kb * 1024) - used;
+            if left <= 0 {
+                return Err("Attachment size limit reached! Delete some files to open space");
+            }
+            Ok(Some(left))
         }
+        None => Ok(None),
+    }
+}
+
+fn attachment_upload_json(attachment: &Attachment, cipher: &Cipher, admin_request: bool, headers: &Headers, conn: &DbConn) -> Value {
+    let claims = generate_file_upload_claims(headers.user.uuid.clone(), cipher.uuid.clone(), attachment.id.clone());
+    let url = format!(
+        "{}/api/ciphers/{}/attachment/{}?token={}",
+        headers.host,
+        cipher.uuid,
+        attachment.id,
+        encode_jwt(&claims)
+    );
+
+    let cipher_json = cipher.to_json(&headers.host, &headers.user.uuid, None, conn);
+    let (cipher_response, cipher_mini_response) = if admin_request {
+        (Value::Null, cipher_json)
+    } else {
+        (cipher_json, Value::Null)
     };
 
-    update_cipher_from_data(
-        &mut cipher,
-        data.Cipher,
-        &headers,
-        shared_to_collection,
-        &conn,
-        &nt,
-        UpdateType::CipherUpdate,
-    )?;
+    json!({
+        "Object": "attachment-fileUpload",
+        "AttachmentId": attachment.id,
+        "Url": url,
+        "FileUploadType": FileUploadType::Direct as i32,
+        "CipherResponse": cipher_response,
+        "CipherMiniResponse": cipher_mini_response,
+    })
+}
+
+/// Creates the attachment metadata, the file is uploaded afterwards to the returned URL
+#[post("/ciphers/<uuid>/attachment/v2", format = "application/json", data = "<data>", rank = 1)]
+fn post_attachment_v2(uuid: String, data: JsonUpcase<AttachmentRequestData>, headers: Headers, conn: DbConn) -> JsonResult {
+    let data: AttachmentRequestData = data.into_inner().data;
+
+    let cipher = match Cipher::find_by_uuid(&uuid, &conn) {
+        Some(cipher) => cipher,
+        None => err!("Cipher doesn't exist"),
+    };
 
-    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, &conn)))
+    if !cipher.is_write_accessible_to_user(&headers.user.uuid, &conn) {
+        err!("Cipher is not write accessible")
+    }
+
+    if data.FileSize < 0 {
+        err!("Attachment size can't be negative")
+    }
+
+    match attachment_size_left(&cipher, &conn) {
+        Ok(Some(left)) if data.FileSize > left => {
+            err!("Attachment size limit reached! Delete some files to open space")
+        }
+        Ok(_) => (),
+        Err(msg) => err!(msg),
+    }
+
+    let attachment_id = HEXLOWER.encode(&crypto::get_random(vec![0; 10]));
+    let mut attachment = Attachment::new(attachment_id, cipher.uuid.clone(), data.FileName, data.FileSize);
+    attachment.akey = Some(data.Key);
+    attachment.save(&conn)?;
+
+    let admin_request = data.AdminRequest.unwrap_or(false);
+    Ok(Json(attachment_upload_json(&attachment, &cipher, admin_request, &headers, &conn)))
 }
 
-#[post("/ciphers/<uuid>/attachment", format = "multipart/form-data", data = "<data>")]
-fn post_attachment(
+#[get("/ciphers/<uuid>/attachment/<attachment_id>")]
+fn get_attachment(uuid: String, attachment_id: String, headers: Headers, conn: DbConn) -> JsonResult {
+    let cipher = match Cipher::find_by_uuid(&uuid, &conn) {
+        Some(cipher) => cipher,
+        None => err!("Cipher doesn't exist"),
+    };
+
+    if !cipher.is_accessible_to_user(&headers.user.uuid, &conn) {
+        err!("Cipher is not owned by user")
+    }
+
+    // The clients request this before downloading the file, to get a signed download URL
+    match Attachment::find_by_id(&attachment_id, &conn) {
+        Some(attachment) if attachment.cipher_uuid == uuid => {
+            let mut attachment_json = attachment.to_json(&headers.host);
+            attachment_json["Url"] = Value::String(attachment.get_download_url(&headers.host));
+            Ok(Json(attachment_json))
+        }
+        _ => err!("Attachment doesn't exist"),
+    }
+}
+
+#[get("/ciphers/<uuid>/attachment/<attachment_id>/renew")]
+fn renew_attachment_upload(uuid: String, attachment_id: String, headers: Headers, conn: DbConn) -> JsonResult {
+    let attachment = match Attachment::find_by_id(&attachment_id, &conn) {
+        Some(attachment) if attachment.cipher_uuid == uuid => attachment,
+        _ => err!("Attachment doesn't exist"),
+    };
+
+    let cipher = match Cipher::find_by_uuid(&uuid, &conn) {
+        Some(cipher) => cipher,
+        None => err!("Cipher doesn't exist"),
+    };
+
+    if !cipher.is_write_accessible_to_user(&headers.user.uuid, &conn) {
+        err!("Cipher is not write accessible")
+    }
+
+    Ok(Json(attachment_upload_json(&attachment, &cipher, false, &headers, &conn)))
+}
+
+/// Upload of the file of a v2 attachment, authenticated with the token included in the upload URL
+#[post(
+    "/ciphers/<uuid>/attachment/<attachment_id>?<token>",
+    format = "multipart/form-data",
+    data = "<data>",
+    rank = 2
+)]
+fn post_attachment_v2_data_token(
     uuid: String,
+    attachment_id: String,
+    token: String,
+    data: Data,
+    content_type: &ContentType,
+    conn: DbConn,
+    nt: Notify,
+) -> EmptyResult {
+    let claims = match decode_file_upload(&token) {
+        Ok(claims) => claims,
+        Err(_) => err_discard!("Invalid upload token", data),
+    };
+
+    if claims.cipher_id != uuid || claims.attachment_id != attachment_id {
+        err_discard!("Upload token is not valid for this attachment", data)
+    }
+
+    save_v2_attachment_data(&uuid, &attachment_id, &claims.sub, data, content_type, &conn, &nt)
+}
+
+/// Upload of the file of a v2 attachment, used by the official clients with the direct upload type
+#[post("/ciphers/<uuid>/attachment/<attachment_id>", format = "multipart/form-data", data = "<data>", rank = 3)]
+fn post_attachment_v2_data(
+    uuid: String,
+    attachment_id: String,
     data: Data,
     content_type: &ContentType,
     headers: Headers,
     conn: DbConn,
     nt: Notify,
-) -> JsonResult {
-    let cipher = match Cipher::find_by_uuid(&uuid, &conn) {
+) -> EmptyResult {
+    save_v2_attachment_data(&uuid, &attachment_id, &headers.user.uuid, data, content_type, &conn, &nt)
+}
+
+fn save_v2_attachment_data(
+    uuid: &str,
+    attachment_id: &str,
+    user_uuid: &str,
+    data: Data,
+    content_type: &ContentType,
+    conn: &DbConn,
+    nt: &Notify,
+) -> EmptyResult {
+    let attachment = match Attachment::find_by_id(attachment_id, conn) {
+        Some(attachment) if attachment.cipher_uuid == uuid => attachment,
+        _ => err_discard!("Attachment doesn't exist", data),
+    };
+
+    save_attachment(Some(attachment), uuid, data, content_type, user_uuid, conn, nt)?;
+    Ok(())
+}
+
+/// Saves the file received in the multipart `data`. When `attachment` is `None` (legacy flow), the metadata is
+/// created from the multipart fields, otherwise the file is stored for the already existing v2 attachment.
+fn save_attachment(
+    mut attachment: Option<Attachment>,
+    cipher_uuid: &str,
+    data: Data,
+    content_type: &ContentType,
+    user_uuid: &str,
+    conn: &DbConn,
+    nt: &Notify,
+) -> Result<Cipher, crate::error::Error> {
+    let cipher = match Cipher::find_by_uuid(cipher_uuid, conn) {
         Some(cipher) => cipher,
         None => err_discard!("Cipher doesn't exist", data),
     };
 
-    if !cipher.is_write_accessible_to_user(&headers.user.uuid, &conn) {
+    if !cipher.is_write_accessible_to_user(user_uuid, conn) {
         err_discard!("Cipher is not write accessible", data)
     }
 
@@ -784,35 +1044,17 @@ fn post_attachment(
     let boundary_pair = params.next().expect("No boundary provided");
     let boundary = boundary_pair.1;
 
-    let size_limit = if let Some(ref user_uuid) = cipher.user_uuid {
-        match CONFIG.user_attachment_limit() {
-            Some(0) => err_discard!("Attachments are disabled", data),
-            Some(limit_kb) => {
-                let left = (limit_kb * 1024) - Attachment::size_by_user(user_uuid, &conn);
-                if left <= 0 {
-                    err_discard!("Attachment size limit reached! Delete some files to open space", data)
-                }
-                Some(left as u64)
-            }
-            None => None,
-        }
-    } else if let Some(ref org_uuid) = cipher.organization_uuid {
-        match CONFIG.org_attachment_limit() {
-            Some(0) => err_discard!("Attachments are disabled", data),
-            Some(limit_kb) => {
-                let left = (limit_kb * 1024) - Attachment::size_by_org(org_uuid, &conn);
-                if left <= 0 {
-                    err_discard!("Attachment size limit reached! Delete some files to open space", data)
-                }
-                Some(left as u64)
-            }
-            None => None,
-        }
-    } else {
-        err_discard!("Cipher is neither owned by a user nor an organization", data);
+    let size_limit = match attachment {
+        // The declared size was already checked against the limits when the attachment was created
+        Some(ref attachment) => Some(attachment.file_size),
+        None => match attachment_size_left(&cipher, conn) {
+            Ok(left) => left,
+            Err(msg) => err_discard!(msg, data),
+        },
     };
 
-    let base_path = Path::new(&CONFIG.attachments_folder()).join(&cipher.uuid);
+    // The uploads are received in the temporary folder and then moved into the configured storage
+    let tmp_path = Path::new(&CONFIG.tmp_folder()).to_path_buf();
 
     let mut attachment_key = None;
     let mut error = None;
@@ -828,14 +1070,15 @@ fn post_attachment(
                     }
                 }
                 "data" => {
-                    // This is provided by the client, don't trust it
-                    let name = field.headers.filename.expect("No filename provided");
-
-                    let file_name = HEXLOWER.encode(&crypto::get_random(vec![0; 10]));
-                    let path = base_path.join(&file_name);
+                    let file_id = match attachment {
+                        Some(ref attachment) => attachment.id.clone(),
+                        None => HEXLOWER.encode(&crypto::get_random(vec![0; 10])),
+                    };
+                    let path = tmp_path.join(format!("{}_{}", cipher.uuid, file_id));
 
+                    let size_limit = size_limit.map(|l| l as u64);
                     let size = match field.data.save().memory_threshold(0).size_limit(size_limit).with_path(path.clone()) {
-                        SaveResult::Full(SavedData::File(_, size)) => size as i32,
+                        SaveResult::Full(SavedData::File(_, size)) => size as i64,
                         SaveResult::Full(other) => {
                             std::fs::remove_file(path).ok();
                             error = Some(format!("Attachment is not a file: {:?}", other));
@@ -853,9 +1096,23 @@ fn post_attachment(
                         }
                     };
 
-                    let mut attachment = Attachment::new(file_name, cipher.uuid.clone(), name, size);
-                    attachment.akey = attachment_key.clone();
-                    attachment.save(&conn).expect("Error saving attachment");
+                    let mut new_attachment = match attachment.take() {
+                        Some(attachment) => attachment,
+                        None => {
+                            // This is provided by the client, don't trust it
+                            let name = field.headers.filename.expect("No filename provided");
+                            let mut attachment = Attachment::new(file_id, cipher.uuid.clone(), name, size);
+                            attachment.akey = attachment_key.clone();
+                            attachment
+                        }
+                    };
+                    new_attachment.file_size = size;
+
+                    if let Err(e) = storage::attachments().store_file(&new_attachment.get_storage_key(), &path) {
+                        error = Some(format!("Error storing attachment: {:?}", e));
+                        return;
+                    }
+                    new_attachment.save(conn).expect("Error saving attachment");
                 }
                 _ => error!("Invalid multipart name"),
             }
@@ -866,9 +1123,23 @@ fn post_attachment(
         err!(e);
     }
 
-    nt.send_cipher_update(UpdateType::CipherUpdate, &cipher, &cipher.update_users_revision(&conn));
+    nt.send_cipher_update(UpdateType::CipherUpdate, &cipher, &cipher.update_users_revision(conn));
+
+    Ok(cipher)
+}
+
+#[post("/ciphers/<uuid>/attachment", format = "multipart/form-data", data = "<data>")]
+fn post_attachment(
+    uuid: String,
+    data: Data,
+    content_type: &ContentType,
+    headers: Headers,
+    conn: DbConn,
+    nt: Notify,
+) -> JsonResult {
+    let cipher = save_attachment(None, &uuid, data, content_type, &headers.user.uuid, &conn, &nt)?;
 
-    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, &conn)))
+    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, None, &conn)))
 }
 
 #[post("/ciphers/<uuid>/attachment-admin", format = "multipart/form-data", data = "<data>")]
@@ -1176,7 +1447,7 @@ fn _restore_cipher_by_uuid(uuid: &str, headers: &Headers, conn: &DbConn, nt: &No
     cipher.save(&conn)?;
 
     nt.send_cipher_update(UpdateType::CipherUpdate, &cipher, &cipher.update_users_revision(&conn));
-    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, &conn)))
+    Ok(Json(cipher.to_json(&headers.host, &headers.user.uuid, None, &conn)))
 }
 
 fn _restore_multiple_ciphers(data: JsonUpcase<Value>, headers: &Headers, conn: &DbConn, nt: &Notify) -> JsonResult {
@@ -0,0 +1,151 @@
+use rocket_contrib::json::Json;
+use serde_json::Value;
+
+use crate::{
+    api::{EmptyResult, JsonResult, JsonUpcase, PasswordData},
+    auth::Headers,
+    db::{models::*, DbConn},
+};
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        get_devices,
+        get_device,
+        put_device,
+        post_device,
+        deactivate_device,
+        deactivate_device_put,
+        logout_other_devices,
+        clear_device_token,
+        put_device_token,
+    ]
+}
+
+#[get("/devices")]
+fn get_devices(headers: Headers, conn: DbConn) -> JsonResult {
+    let devices = Device::find_by_user(&headers.user.uuid, &conn);
+
+    let devices_json: Vec<Value> = devices
+        .iter()
+        .map(|d| {
+            let mut device = d.to_json();
+            device["Current"] = json!(d.uuid == headers.device.uuid);
+            device
+        })
+        .collect();
+
+    Ok(Json(json!({
+      "Data": devices_json,
+      "Object": "list",
+      "ContinuationToken": null,
+    })))
+}
+
+#[get("/devices/<uuid>")]
+fn get_device(uuid: String, headers: Headers, conn: DbConn) -> JsonResult {
+    let device = match Device::find_by_uuid_and_user(&uuid, &headers.user.uuid, &conn) {
+        Some(device) => device,
+        None => err!("Invalid device"),
+    };
+
+    let mut device_json = device.to_json();
+    device_json["Current"] = json!(device.uuid == headers.device.uuid);
+    Ok(Json(device_json))
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct DeviceData {
+    Name: String,
+}
+
+#[put("/devices/<uuid>", data = "<data>")]
+fn put_device(uuid: String, data: JsonUpcase<DeviceData>, headers: Headers, conn: DbConn) -> JsonResult {
+    let data: DeviceData = data.into_inner().data;
+
+    let mut device = match Device::find_by_uuid_and_user(&uuid, &headers.user.uuid, &conn) {
+        Some(device) => device,
+        None => err!("Invalid device"),
+    };
+
+    let name = data.Name.trim();
+    if name.is_empty() || name.chars().count() > 100 {
+        err!("The device name must be between 1 and 100 characters")
+    }
+
+    device.name = name.to_string();
+    device.save(&conn)?;
+
+    Ok(Json(device.to_json()))
+}
+
+#[post("/devices/<uuid>", data = "<data>")]
+fn post_device(uuid: String, data: JsonUpcase<DeviceData>, headers: Headers, conn: DbConn) -> JsonResult {
+    put_device(uuid, data, headers, conn)
+}
+
+#[post("/devices/<uuid>/deactivate")]
+fn deactivate_device(uuid: String, headers: Headers, conn: DbConn) -> EmptyResult {
+    let mut device = match Device::find_by_uuid_and_user(&uuid, &headers.user.uuid, &conn) {
+        Some(device) => device,
+        None => err!("Invalid device"),
+    };
+
+    device.revoke();
+    device.save(&conn)
+}
+
+#[put("/devices/<uuid>/deactivate")]
+fn deactivate_device_put(uuid: String, headers: Headers, conn: DbConn) -> EmptyResult {
+    deactivate_device(uuid, headers, conn)
+}
+
+#[post("/devices/logout-others", data = "<data>")]
+fn logout_other_devices(data: JsonUpcase<PasswordData>, headers: Headers, conn: DbConn) -> EmptyResult {
+    let data: PasswordData = data.into_inner().data;
+
+    if !headers.user.check_valid_password(&data.MasterPasswordHash) {
+        err!("Invalid password")
+    }
+
+    conn.transaction(|| {
+        for mut device in Device::find_by_user(&headers.user.uuid, &conn) {
+            if device.uuid != headers.device.uuid && !device.is_revoked() {
+                device.revoke();
+                device.save(&conn)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[put("/devices/identifier/<uuid>/clear-token")]
+fn clear_device_token(uuid: String) -> EmptyResult {
+    // This endpoint doesn't have auth header
+
+    let _ = uuid;
+    // uuid is not related to deviceId
+
+    // This only clears push token
+    // https://github.com/bitwarden/core/blob/master/src/Api/Controllers/DevicesController.cs#L109
+    // https://github.com/bitwarden/core/blob/master/src/Core/Services/Implementations/DeviceService.cs#L37
+    Ok(())
+}
+
+#[put("/devices/identifier/<uuid>/token", data = "<data>")]
+fn put_device_token(uuid: String, data: JsonUpcase<Value>, headers: Headers) -> JsonResult {
+    let _data: Value = data.into_inner().data;
+    // Data has a single string value "PushToken"
+    let _ = uuid;
+    // uuid is not related to deviceId
+
+    // TODO: This should save the push token, but we don't have push functionality
+
+    Ok(Json(json!({
+        "Id": headers.device.uuid,
+        "Name": headers.device.name,
+        "Type": headers.device.atype,
+        "Identifier": headers.device.uuid,
+        "CreationDate": crate::util::format_date(&headers.device.created_at),
+    })))
+}
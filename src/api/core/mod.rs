@@ -1,22 +1,23 @@
 mod accounts;
 mod ciphers;
+mod devices;
 mod folders;
 mod organizations;
 pub mod two_factor;
 
 pub fn routes() -> Vec<Route> {
     let mut mod_routes = routes![
-        clear_device_token,
-        put_device_token,
         get_eq_domains,
         post_eq_domains,
         put_eq_domains,
         hibp_breach,
+        hibp_range,
     ];
 
     let mut routes = Vec::new();
     routes.append(&mut accounts::routes());
     routes.append(&mut ciphers::routes());
+    routes.append(&mut devices::routes());
     routes.append(&mut folders::routes());
     routes.append(&mut organizations::routes());
     routes.append(&mut two_factor::routes());
@@ -28,48 +29,26 @@ pub fn routes() -> Vec<Route> {
 //
 // Move this somewhere else
 //
-use rocket::Route;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use rocket::{http::ContentType, response::Content, Route};
 use rocket_contrib::json::Json;
 use serde_json::Value;
 
 use crate::{
-    api::{EmptyResult, JsonResult, JsonUpcase},
+    api::{ApiResult, EmptyResult, JsonResult, JsonUpcase},
     auth::Headers,
-    db::DbConn,
+    db::{models::HibpCache, DbConn},
     error::Error,
+    util::{read_file_string, Cached},
+    CONFIG,
 };
 
-#[put("/devices/identifier/<uuid>/clear-token")]
-fn clear_device_token(uuid: String) -> EmptyResult {
-    // This endpoint doesn't have auth header
-
-    let _ = uuid;
-    // uuid is not related to deviceId
-
-    // This only clears push token
-    // https://github.com/bitwarden/core/blob/master/src/Api/Controllers/DevicesController.cs#L109
-    // https://github.com/bitwarden/core/blob/master/src/Core/Services/Implementations/DeviceService.cs#L37
-    Ok(())
-}
-
-#[put("/devices/identifier/<uuid>/token", data = "<data>")]
-fn put_device_token(uuid: String, data: JsonUpcase<Value>, headers: Headers) -> JsonResult {
-    let _data: Value = data.into_inner().data;
-    // Data has a single string value "PushToken"
-    let _ = uuid;
-    // uuid is not related to deviceId
-
-    // TODO: This should save the push token, but we don't have push functionality
-
-    Ok(Json(json!({
-        "Id": headers.device.uuid,
-        "Name": headers.device.name,
-        "Type": headers.device.atype,
-        "Identifier": headers.device.uuid,
-        "CreationDate": crate::util::format_date(&headers.device.created_at),
-    })))
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 #[allow(non_snake_case)]
 struct GlobalDomain {
@@ -82,11 +61,11 @@ const GLOBAL_DOMAINS: &str = include_str!("../../static/global_domains.json");
 
 #[get("/settings/domains")]
 fn get_eq_domains(headers: Headers) -> JsonResult {
-    _get_eq_domains(headers, false)
+    _get_eq_domains(&headers, false)
 }
 
-fn _get_eq_domains(headers: Headers, no_excluded: bool) -> JsonResult {
-    let user = headers.user;
+fn _get_eq_domains(headers: &Headers, no_excluded: bool) -> JsonResult {
+    let user = &headers.user;
     use serde_json::from_str;
 
     let equivalent_domains: Vec<Vec<String>> = from_str(&user.equivalent_domains).unwrap();
@@ -140,44 +119,119 @@ fn put_eq_domains(data: JsonUpcase<EquivDomainData>, headers: Headers, conn: DbC
 }
 
 #[get("/hibp/breach?<username>")]
-fn hibp_breach(username: String) -> JsonResult {
-    let user_agent = "Bitwarden_RS";
+fn hibp_breach(username: String, headers: Headers, conn: DbConn) -> JsonResult {
+    let api_key = match CONFIG.hibp_api_key() {
+        Some(api_key) => api_key,
+        None => return Ok(Json(hibp_manual_check(&username))),
+    };
+
+    let cache_ttl = CONFIG.hibp_cache_ttl() as i64;
+    if cache_ttl > 0 {
+        if let Some(cached) = HibpCache::find_by_account(&username, cache_ttl, &conn) {
+            return hibp_breach_response(cached.response);
+        }
+    }
+
+    // Only the requests to HaveIBeenPwned count towards the limit
+    check_hibp_rate_limit(&headers.user.uuid)?;
+
+    use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
     let url = format!(
         "https://haveibeenpwned.com/api/v3/breachedaccount/{}?truncateResponse=false&includeUnverified=false",
-        username
+        utf8_percent_encode(&username, NON_ALPHANUMERIC)
     );
 
     use reqwest::{blocking::Client, header::USER_AGENT};
 
-    if let Some(api_key) = crate::CONFIG.hibp_api_key() {
-        let hibp_client = Client::builder().build()?;
+    let hibp_client = Client::builder().build()?;
 
-        let res = hibp_client
-            .get(&url)
-            .header(USER_AGENT, user_agent)
-            .header("hibp-api-key", api_key)
-            .send()?;
+    let res = hibp_client
+        .get(&url)
+        .header(USER_AGENT, "Bitwarden_RS")
+        .header("hibp-api-key", api_key)
+        .send()?;
 
-        // If we get a 404, return a 404, it means no breached accounts
-        if res.status() == 404 {
-            return Err(Error::empty().with_code(404));
+    // If we get a 404, it means no breached accounts
+    let response = if res.status() == 404 {
+        None
+    } else {
+        Some(res.error_for_status()?.text()?)
+    };
+
+    if cache_ttl > 0 {
+        if let Err(e) = HibpCache::delete_expired(cache_ttl, &conn) {
+            error!("Error deleting expired HIBP cache entries: {:#?}", e);
+        }
+        if let Err(e) = HibpCache::new(&username, response.clone()).save(&conn) {
+            error!("Error caching HIBP response: {:#?}", e);
         }
+    }
 
-        let value: Value = res.error_for_status()?.json()?;
-        Ok(Json(value))
-    } else {
-        Ok(Json(json!([{
-            "Name": "HaveIBeenPwned",
-            "Title": "Manual HIBP Check",
-            "Domain": "haveibeenpwned.com",
-            "BreachDate": "2019-08-18T00:00:00Z",
-            "AddedDate": "2019-08-18T00:00:00Z",
-            "Description": format!("Go to: <a href=\"https://haveibeenpwned.com/account/{account}\" target=\"_blank\" rel=\"noreferrer\">https://haveibeenpwned.com/account/{account}</a> for a manual check.<br/><br/>HaveIBeenPwned API key not set!<br/>Go to <a href=\"https://haveibeenpwned.com/API/Key\" target=\"_blank\" rel=\"noreferrer\">https://haveibeenpwned.com/API/Key</a> to purchase an API key from HaveIBeenPwned.<br/><br/>", account=username),
-            "LogoPath": "bwrs_static/hibp.png",
-            "PwnCount": 0,
-            "DataClasses": [
-                "Error - No API key set!"
-            ]
-        }])))
+    hibp_breach_response(response)
+}
+
+fn hibp_breach_response(response: Option<String>) -> JsonResult {
+    match response {
+        Some(response) => Ok(Json(serde_json::from_str(&response)?)),
+        None => Err(Error::empty().with_code(404)),
+    }
+}
+
+fn hibp_manual_check(username: &str) -> Value {
+    json!([{
+        "Name": "HaveIBeenPwned",
+        "Title": "Manual HIBP Check",
+        "Domain": "haveibeenpwned.com",
+        "BreachDate": "2019-08-18T00:00:00Z",
+        "AddedDate": "2019-08-18T00:00:00Z",
+        "Description": format!("Go to: <a href=\"https://haveibeenpwned.com/account/{account}\" target=\"_blank\" rel=\"noreferrer\">https://haveibeenpwned.com/account/{account}</a> for a manual check.<br/><br/>HaveIBeenPwned API key not set!<br/>Go to <a href=\"https://haveibeenpwned.com/API/Key\" target=\"_blank\" rel=\"noreferrer\">https://haveibeenpwned.com/API/Key</a> to purchase an API key from HaveIBeenPwned.<br/><br/>", account=username),
+        "LogoPath": "bwrs_static/hibp.png",
+        "PwnCount": 0,
+        "DataClasses": [
+            "Error - No API key set!"
+        ]
+    }])
+}
+
+// Lookups made by each user in the current period. The server API key shouldn't be usable to check lots of accounts
+static HIBP_LOOKUPS: Lazy<Mutex<HashMap<String, (Instant, u32)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+const HIBP_RATE_LIMIT_PERIOD: Duration = Duration::from_secs(3600);
+
+fn check_hibp_rate_limit(user_uuid: &str) -> EmptyResult {
+    let limit = CONFIG.hibp_rate_limit();
+    if limit == 0 {
+        return Ok(());
+    }
+
+    let now = Instant::now();
+    let mut lookups = HIBP_LOOKUPS.lock().unwrap();
+    lookups.retain(|_, (start, _)| now.duration_since(*start) < HIBP_RATE_LIMIT_PERIOD);
+
+    let (_, count) = lookups.entry(user_uuid.to_string()).or_insert((now, 0));
+    if *count >= limit {
+        return Err(Error::new("Too many breach reports requested, try again later", "").with_code(429));
+    }
+
+    *count += 1;
+    Ok(())
+}
+
+/// Serves the password hash ranges from the local copy of the HaveIBeenPwned dataset, using the same format as
+/// https://api.pwnedpasswords.com/range/<prefix>, so the passwords can be checked without internet access
+#[get("/hibp/range/<prefix>")]
+fn hibp_range(prefix: String) -> ApiResult<Cached<Content<String>>> {
+    let folder = match CONFIG.hibp_range_folder() {
+        Some(folder) => folder,
+        None => return Err(Error::empty().with_code(404)),
+    };
+
+    if prefix.len() != 5 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        err!("The prefix must be the first 5 hexadecimal characters of a SHA-1 hash")
+    }
+
+    let path = format!("{}/{}.txt", folder, prefix.to_uppercase());
+    match read_file_string(&path) {
+        Ok(range) => Ok(Cached::short(Content(ContentType::Plain, range))),
+        Err(e) => err!("Password range not found", format!("{}: {:?}", path, e)),
     }
 }
@@ -238,14 +238,17 @@ fn post_organization_collections(
     };
 
     let collection = Collection::new(org.uuid, data.Name);
-    collection.save(&conn)?;
-
-    // If the user doesn't have access to all collections, only in case of a Manger,
-    // then we need to save the creating user uuid (Manager) to the users_collection table.
-    // Else the user will not have access to his own created collection.
-    if !user_org.access_all {
-        CollectionUser::save(&headers.user.uuid, &collection.uuid, false, false, &conn)?;
-    }
+    conn.transaction(|| {
+        collection.save(&conn)?;
+
+        // If the user doesn't have access to all collections, only in case of a Manger,
+        // then we need to save the creating user uuid (Manager) to the users_collection table.
+        // Else the user will not have access to his own created collection.
+        if !user_org.access_all {
+            CollectionUser::save(&headers.user.uuid, &collection.uuid, false, false, &conn)?;
+        }
+        Ok(())
+    })?;
 
     Ok(Json(collection.to_json()))
 }
@@ -443,9 +446,10 @@ struct OrgIdData {
 #[get("/ciphers/organization-details?<data..>")]
 fn get_org_details(data: Form<OrgIdData>, headers: Headers, conn: DbConn) -> JsonResult {
     let ciphers = Cipher::find_by_org(&data.organization_id, &conn);
+    let cipher_sync_data = CipherSyncData::new(&headers.user.uuid, &ciphers, &conn);
     let ciphers_json: Vec<Value> = ciphers
         .iter()
-        .map(|c| c.to_json(&headers.host, &headers.user.uuid, &conn))
+        .map(|c| c.to_json(&headers.host, &headers.user.uuid, Some(&cipher_sync_data), &conn))
         .collect();
 
     Ok(Json(json!({
@@ -519,6 +523,8 @@ fn send_invite(org_id: String, data: JsonUpcase<InviteData>, headers: AdminHeade
                 }
 
                 let mut user = User::new(email.clone());
+                // Until the new user sets their own language, assume it's the same as the one inviting them
+                user.culture = headers.user.culture.clone();
                 user.save(&conn)?;
                 user_org_status = UserOrgStatus::Invited as i32;
                 user
@@ -567,6 +573,7 @@ fn send_invite(org_id: String, data: JsonUpcase<InviteData>, headers: AdminHeade
                 Some(new_user.uuid),
                 &org_name,
                 Some(headers.user.email.clone()),
+                &user.culture,
             )?;
         }
     }
@@ -611,6 +618,7 @@ fn reinvite_user(org_id: String, user_org: String, headers: AdminHeaders, conn:
             Some(user_org.uuid),
             &org_name,
             Some(headers.user.email),
+            &user.culture,
         )?;
     } else {
         let invitation = Invitation::new(user.email);
@@ -633,8 +641,8 @@ fn accept_invite(_org_id: String, _org_user_id: String, data: JsonUpcase<AcceptD
     let token = &data.Token;
     let claims = decode_invite(&token)?;
 
-    match User::find_by_mail(&claims.email, &conn) {
-        Some(_) => {
+    let culture = match User::find_by_mail(&claims.email, &conn) {
+        Some(user) => {
             Invitation::take(&claims.email, &conn);
 
             if let (Some(user_org), Some(org)) = (&claims.user_org_id, &claims.org_id) {
@@ -650,9 +658,11 @@ fn accept_invite(_org_id: String, _org_user_id: String, data: JsonUpcase<AcceptD
                 user_org.status = UserOrgStatus::Accepted as i32;
                 user_org.save(&conn)?;
             }
+
+            user.culture
         }
         None => err!("Invited user not found"),
-    }
+    };
 
     if CONFIG.mail_enabled() {
         let mut org_name = CONFIG.invitation_org_name();
@@ -664,10 +674,14 @@ fn accept_invite(_org_id: String, _org_user_id: String, data: JsonUpcase<AcceptD
         };
         if let Some(invited_by_email) = &claims.invited_by_email {
             // User was invited to an organization, so they must be confirmed manually after acceptance
-            mail::send_invite_accepted(&claims.email, invited_by_email, &org_name)?;
+            let inviter_culture = match User::find_by_mail(invited_by_email, &conn) {
+                Some(inviter) => inviter.culture,
+                None => User::CULTURE_DEFAULT.to_string(),
+            };
+            mail::send_invite_accepted(&claims.email, invited_by_email, &org_name, &inviter_culture)?;
         } else {
             // User was invited from /admin, so they are automatically confirmed
-            mail::send_invite_confirmed(&claims.email, &org_name)?;
+            mail::send_invite_confirmed(&claims.email, &org_name, &culture)?;
         }
     }
 
@@ -708,11 +722,11 @@ fn confirm_invite(
             Some(org) => org.name,
             None => err!("Error looking up organization."),
         };
-        let address = match User::find_by_uuid(&user_to_confirm.user_uuid, &conn) {
-            Some(user) => user.email,
+        let user = match User::find_by_uuid(&user_to_confirm.user_uuid, &conn) {
+            Some(user) => user,
             None => err!("Error looking up user."),
         };
-        mail::send_invite_confirmed(&address, &org_name)?;
+        mail::send_invite_confirmed(&user.email, &org_name, &user.culture)?;
     }
 
     user_to_confirm.save(&conn)
@@ -832,7 +846,7 @@ fn delete_user(org_id: String, org_user_id: String, headers: AdminHeaders, conn:
         }
     }
 
-    user_to_delete.delete(&conn)
+    conn.transaction(|| user_to_delete.delete(&conn))
 }
 
 #[post("/organizations/<org_id>/users/<org_user_id>/delete")]
@@ -1118,57 +1132,70 @@ fn import(org_id: String, data: JsonUpcase<OrgImportData>, headers: Headers, con
         None => err!("User not part of organization"),
     };
 
-    for user_data in &data.Users {
-        if user_data.Deleted {
-            // If user is marked for deletion and it exists, delete it
-            if let Some(user_org) = UserOrganization::find_by_email_and_org(&user_data.Email, &org_id, &conn) {
-                user_org.delete(&conn)?;
-            }
+    // The invitations are sent once the changes are saved, the mail queue uses its own connection
+    let mut invites = Vec::new();
 
-        // If user is not part of the organization, but it exists
-        } else if UserOrganization::find_by_email_and_org(&user_data.Email, &org_id, &conn).is_none() {
-            if let Some (user) = User::find_by_mail(&user_data.Email, &conn) {
+    conn.transaction(|| {
+        for user_data in &data.Users {
+            if user_data.Deleted {
+                // If user is marked for deletion and it exists, delete it
+                if let Some(user_org) = UserOrganization::find_by_email_and_org(&user_data.Email, &org_id, &conn) {
+                    user_org.delete(&conn)?;
+                }
+
+            // If user is not part of the organization, but it exists
+            } else if UserOrganization::find_by_email_and_org(&user_data.Email, &org_id, &conn).is_none() {
+                if let Some (user) = User::find_by_mail(&user_data.Email, &conn) {
                 
-                let user_org_status = if CONFIG.mail_enabled() {
-                    UserOrgStatus::Invited as i32
-                } else {
-                    UserOrgStatus::Accepted as i32 // Automatically mark user as accepted if no email invites
-                };
+                    let user_org_status = if CONFIG.mail_enabled() {
+                        UserOrgStatus::Invited as i32
+                    } else {
+                        UserOrgStatus::Accepted as i32 // Automatically mark user as accepted if no email invites
+                    };
 
-                let mut new_org_user = UserOrganization::new(user.uuid.clone(), org_id.clone());
-                new_org_user.access_all = false;
-                new_org_user.atype = UserOrgType::User as i32;
-                new_org_user.status = user_org_status;
+                    let mut new_org_user = UserOrganization::new(user.uuid.clone(), org_id.clone());
+                    new_org_user.access_all = false;
+                    new_org_user.atype = UserOrgType::User as i32;
+                    new_org_user.status = user_org_status;
 
-                new_org_user.save(&conn)?;
+                    new_org_user.save(&conn)?;
 
-                if CONFIG.mail_enabled() {
-                    let org_name = match Organization::find_by_uuid(&org_id, &conn) {
-                        Some(org) => org.name,
-                        None => err!("Error looking up organization"),
-                    };
+                    if CONFIG.mail_enabled() {
+                        invites.push((user, new_org_user.uuid));
+                    }
+                }  
+            }
+        }
 
-                    mail::send_invite(
-                        &user_data.Email,
-                        &user.uuid,
-                        Some(org_id.clone()),
-                        Some(new_org_user.uuid),
-                        &org_name,
-                        Some(headers.user.email.clone()),
-                    )?;
-                }
-            }  
+        // If this flag is enabled, any user that isn't provided in the Users list will be removed (by default they will be kept unless they have Deleted == true)
+        if data.OverwriteExisting {
+            for user_org in UserOrganization::find_by_org_and_type(&org_id, UserOrgType::User as i32, &conn) {  
+                if let Some (user_email) = User::find_by_uuid(&user_org.user_uuid, &conn).map(|u| u.email) {
+                    if !data.Users.iter().any(|u| u.Email == user_email) {
+                        user_org.delete(&conn)?;
+                    }
+                } 
+            }
         }
-    }
+        Ok(())
+    })?;
 
-    // If this flag is enabled, any user that isn't provided in the Users list will be removed (by default they will be kept unless they have Deleted == true)
-    if data.OverwriteExisting {
-        for user_org in UserOrganization::find_by_org_and_type(&org_id, UserOrgType::User as i32, &conn) {  
-            if let Some (user_email) = User::find_by_uuid(&user_org.user_uuid, &conn).map(|u| u.email) {
-                if !data.Users.iter().any(|u| u.Email == user_email) {
-                    user_org.delete(&conn)?;
-                }
-            } 
+    if !invites.is_empty() {
+        let org_name = match Organization::find_by_uuid(&org_id, &conn) {
+            Some(org) => org.name,
+            None => err!("Error looking up organization"),
+        };
+
+        for (user, org_user_uuid) in invites {
+            mail::send_invite(
+                &user.email,
+                &user.uuid,
+                Some(org_id.clone()),
+                Some(org_user_uuid),
+                &org_name,
+                Some(headers.user.email.clone()),
+                &user.culture,
+            )?;
         }
     }
 
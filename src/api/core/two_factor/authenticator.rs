@@ -4,7 +4,8 @@ use rocket_contrib::json::Json;
 
 use crate::{
     api::{
-        core::two_factor::_generate_recover_code, EmptyResult, JsonResult, JsonUpcase, NumberOrString, PasswordData,
+        core::two_factor::{_generate_recover_code, _notify_twofactor_change},
+        EmptyResult, JsonResult, JsonUpcase, NumberOrString, PasswordData,
     },
     auth::{ClientIp, Headers},
     crypto,
@@ -88,6 +89,7 @@ fn activate_authenticator(
     validate_totp_code(&user.uuid, token, &key.to_uppercase(), &ip, &conn)?;
 
     _generate_recover_code(&mut user, &conn);
+    _notify_twofactor_change(&user, TwoFactorType::display_name(TwoFactorType::Authenticator as i32), true);
 
     Ok(Json(json!({
         "Enabled": true,
@@ -4,7 +4,10 @@ use rocket::Route;
 use rocket_contrib::json::Json;
 
 use crate::{
-    api::{core::two_factor::_generate_recover_code, ApiResult, EmptyResult, JsonResult, JsonUpcase, PasswordData},
+    api::{
+        core::two_factor::{_generate_recover_code, _notify_twofactor_change},
+        ApiResult, EmptyResult, JsonResult, JsonUpcase, PasswordData,
+    },
     auth::Headers,
     crypto,
     db::{
@@ -169,6 +172,7 @@ fn activate_duo(data: JsonUpcase<EnableDuoData>, headers: Headers, conn: DbConn)
     twofactor.save(&conn)?;
 
     _generate_recover_code(&mut user, &conn);
+    _notify_twofactor_change(&user, TwoFactorType::display_name(TwoFactorType::Duo as i32), true);
 
     Ok(Json(json!({
         "Enabled": true,
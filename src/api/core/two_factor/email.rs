@@ -3,11 +3,14 @@ use rocket::Route;
 use rocket_contrib::json::Json;
 
 use crate::{
-    api::{core::two_factor::_generate_recover_code, EmptyResult, JsonResult, JsonUpcase, PasswordData},
+    api::{
+        core::two_factor::{_generate_recover_code, _notify_twofactor_change},
+        EmptyResult, JsonResult, JsonUpcase, PasswordData,
+    },
     auth::Headers,
     crypto,
     db::{
-        models::{TwoFactor, TwoFactorType},
+        models::{TwoFactor, TwoFactorType, User},
         DbConn,
     },
     error::{Error, MapResult},
@@ -65,7 +68,11 @@ pub fn send_token(user_uuid: &str, conn: &DbConn) -> EmptyResult {
     twofactor.data = twofactor_data.to_json();
     twofactor.save(&conn)?;
 
-    mail::send_token(&twofactor_data.email, &twofactor_data.last_token.map_res("Token is empty")?)?;
+    let culture = match User::find_by_uuid(user_uuid, &conn) {
+        Some(user) => user.culture,
+        None => err!("User not found"),
+    };
+    mail::send_token(&twofactor_data.email, &twofactor_data.last_token.map_res("Token is empty")?, &culture)?;
 
     Ok(())
 }
@@ -132,7 +139,7 @@ fn send_email(data: JsonUpcase<SendEmailData>, headers: Headers, conn: DbConn) -
     );
     twofactor.save(&conn)?;
 
-    mail::send_token(&twofactor_data.email, &twofactor_data.last_token.map_res("Token is empty")?)?;
+    mail::send_token(&twofactor_data.email, &twofactor_data.last_token.map_res("Token is empty")?, &user.culture)?;
 
     Ok(())
 }
@@ -175,6 +182,7 @@ fn email(data: JsonUpcase<EmailData>, headers: Headers, conn: DbConn) -> JsonRes
     twofactor.save(&conn)?;
 
     _generate_recover_code(&mut user, &conn);
+    _notify_twofactor_change(&user, TwoFactorType::display_name(TwoFactorType::Email as i32), true);
 
     Ok(Json(json!({
         "Email": email_data.email,
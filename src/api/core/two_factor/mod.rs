@@ -8,9 +8,10 @@ use crate::{
     auth::Headers,
     crypto,
     db::{
-        models::{TwoFactor, User},
+        models::{TwoFactor, TwoFactorType, User},
         DbConn,
     },
+    mail,
 };
 
 pub mod authenticator;
@@ -95,11 +96,21 @@ fn recover(data: JsonUpcase<RecoverTwoFactor>, conn: DbConn) -> JsonResult {
     }
 
     // Remove all twofactors from the user
+    let removed: Vec<_> = TwoFactor::find_by_user(&user.uuid, &conn)
+        .iter()
+        .filter(|tf| tf.atype < 1000) // Challenges are an implementation detail
+        .map(|tf| TwoFactorType::display_name(tf.atype))
+        .collect();
     TwoFactor::delete_all_by_user(&user.uuid, &conn)?;
 
     // Remove the recovery code, not needed without twofactors
     user.totp_recover = None;
     user.save(&conn)?;
+
+    if !removed.is_empty() {
+        _notify_twofactor_change(&user, &removed.join(", "), false);
+    }
+
     Ok(Json(json!({})))
 }
 
@@ -111,6 +122,19 @@ fn _generate_recover_code(user: &mut User, conn: &DbConn) {
     }
 }
 
+/// Sends the security notification after a two-step login method has been added or removed
+fn _notify_twofactor_change(user: &User, provider: &str, added: bool) {
+    let result = if added {
+        mail::send_twofactor_added(user, provider)
+    } else {
+        mail::send_twofactor_removed(user, provider)
+    };
+
+    if let Err(e) = result {
+        error!("Error sending two-step login change notification: {:#?}", e);
+    }
+}
+
 #[derive(Deserialize)]
 #[allow(non_snake_case)]
 struct DisableTwoFactorData {
@@ -132,6 +156,7 @@ fn disable_twofactor(data: JsonUpcase<DisableTwoFactorData>, headers: Headers, c
 
     if let Some(twofactor) = TwoFactor::find_by_user_and_type(&user.uuid, type_, &conn) {
         twofactor.delete(&conn)?;
+        _notify_twofactor_change(&user, TwoFactorType::display_name(type_), false);
     }
 
     Ok(Json(json!({
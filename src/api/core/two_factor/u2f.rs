@@ -10,8 +10,8 @@ use u2f::{
 
 use crate::{
     api::{
-        core::two_factor::_generate_recover_code, ApiResult, EmptyResult, JsonResult, JsonUpcase, NumberOrString,
-        PasswordData,
+        core::two_factor::{_generate_recover_code, _notify_twofactor_change},
+        ApiResult, EmptyResult, JsonResult, JsonUpcase, NumberOrString, PasswordData,
     },
     auth::Headers,
     db::{
@@ -186,6 +186,7 @@ fn activate_u2f(data: JsonUpcase<EnableU2FData>, headers: Headers, conn: DbConn)
     save_u2f_registrations(&user.uuid, &regs, &conn)?;
 
     _generate_recover_code(&mut user, &conn);
+    _notify_twofactor_change(&user, TwoFactorType::display_name(TwoFactorType::U2f as i32), true);
 
     let keys_json: Vec<Value> = regs.iter().map(U2FRegistration::to_json).collect();
     Ok(Json(json!({
@@ -228,6 +229,7 @@ fn delete_u2f(data: JsonUpcase<DeleteU2FData>, headers: Headers, conn: DbConn) -
         Err(_) => err!("Error parsing U2F data"),
     };
 
+    let registrations = data.len();
     data.retain(|r| r.id != id);
 
     let new_data_str = serde_json::to_string(&data)?;
@@ -235,6 +237,10 @@ fn delete_u2f(data: JsonUpcase<DeleteU2FData>, headers: Headers, conn: DbConn) -
     tf.data = new_data_str;
     tf.save(&conn)?;
 
+    if data.len() != registrations {
+        _notify_twofactor_change(&headers.user, TwoFactorType::display_name(type_), false);
+    }
+
     let keys_json: Vec<Value> = data.iter().map(U2FRegistration::to_json).collect();
 
     Ok(Json(json!({
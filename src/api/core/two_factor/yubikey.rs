@@ -4,7 +4,10 @@ use serde_json::Value;
 use yubico::{config::Config, verify};
 
 use crate::{
-    api::{core::two_factor::_generate_recover_code, EmptyResult, JsonResult, JsonUpcase, PasswordData},
+    api::{
+        core::two_factor::{_generate_recover_code, _notify_twofactor_change},
+        EmptyResult, JsonResult, JsonUpcase, PasswordData,
+    },
     auth::Headers,
     db::{
         models::{TwoFactor, TwoFactorType},
@@ -157,6 +160,7 @@ fn activate_yubikey(data: JsonUpcase<EnableYubikeyData>, headers: Headers, conn:
     yubikey_data.save(&conn)?;
 
     _generate_recover_code(&mut user, &conn);
+    _notify_twofactor_change(&user, TwoFactorType::display_name(TwoFactorType::YubiKey as i32), true);
 
     let mut result = jsonify_yubikeys(yubikey_metadata.Keys);
 
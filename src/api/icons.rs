@@ -1,18 +1,34 @@
 use std::{
     collections::HashMap,
-    fs::{create_dir_all, remove_file, symlink_metadata, File},
+    fs::{create_dir_all, read_dir, remove_file, symlink_metadata, File},
     io::prelude::*,
     net::{IpAddr, ToSocketAddrs},
-    sync::RwLock,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+    thread,
     time::{Duration, SystemTime},
 };
 
 use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::{blocking::Client, blocking::Response, header, Url};
-use rocket::{http::ContentType, http::Cookie, response::Content, Route};
+use rocket::{
+    http::{ContentType, Cookie},
+    request::Request,
+    response::{self, Content, Redirect, Responder},
+    Route,
+};
+
+use serde_json::Value;
 
-use crate::{error::Error, util::Cached, CONFIG};
+use crate::{
+    error::Error,
+    storage,
+    util::{get_display_size, Cached},
+    CONFIG,
+};
 
 pub fn routes() -> Vec<Route> {
     routes![icon]
@@ -44,14 +60,48 @@ static ICON_SIZE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?x)(\d+)\D*(\d+
 // Special HashMap which holds the user defined Regex to speedup matching the regex.
 static ICON_BLACKLIST_REGEX: Lazy<RwLock<HashMap<String, Regex>>> = Lazy::new(|| RwLock::new(HashMap::new()));
 
+enum IconResponse {
+    Icon(Cached<Content<Vec<u8>>>),
+    Redirect(Redirect),
+}
+
+impl<'r> Responder<'r> for IconResponse {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        match self {
+            IconResponse::Icon(icon) => icon.respond_to(req),
+            IconResponse::Redirect(redirect) => redirect.respond_to(req),
+        }
+    }
+}
+
 #[get("/<domain>/icon.png")]
-fn icon(domain: String) -> Option<Cached<Content<Vec<u8>>>> {
+fn icon(domain: String) -> Option<IconResponse> {
     if !is_valid_domain(&domain) {
         warn!("Invalid domain: {}", domain);
         return None;
     }
 
-    get_icon(&domain).map(|icon| Cached::long(Content(ContentType::new("image", "x-icon"), icon)))
+    let service_url = CONFIG._icon_service_url();
+    if !service_url.is_empty() {
+        let url = service_url.replace("{}", &domain);
+        let redirect = match CONFIG.icon_redirect_code() {
+            301 => Redirect::moved(url),
+            307 => Redirect::temporary(url),
+            308 => Redirect::permanent(url),
+            _ => Redirect::found(url),
+        };
+        return Some(IconResponse::Redirect(redirect));
+    }
+
+    match get_icon(&domain) {
+        Some(icon) => {
+            let content_type = IconFormat::detect(&icon).map_or(ContentType::PNG, IconFormat::content_type);
+            Some(IconResponse::Icon(Cached::long(Content(content_type, icon))))
+        }
+        // The generated icons are cached for less time, so the real one is shown soon once it can be downloaded
+        None => fallback_icon(&domain)
+            .map(|(content_type, icon)| IconResponse::Icon(Cached::short(Content(content_type, icon)))),
+    }
 }
 
 /// Returns if the domain provided is valid or not.
@@ -239,17 +289,21 @@ fn is_domain_blacklisted(domain: &str) -> bool {
 }
 
 fn get_icon(domain: &str) -> Option<Vec<u8>> {
-    let path = format!("{}/{}.png", CONFIG.icon_cache_folder(), domain);
+    let path = format!("{}/{}.png", CONFIG.icon_cache_folder(), storage::cache_file_name(domain));
 
     // Check for expiration of negatively cached copy
     if icon_is_negcached(&path) {
+        CACHE_NEGATIVE_HITS.fetch_add(1, Ordering::Relaxed);
         return None;
     }
 
     if let Some(icon) = get_cached_icon(&path) {
+        CACHE_HITS.fetch_add(1, Ordering::Relaxed);
         return Some(icon);
     }
 
+    CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+
     if CONFIG.disable_icon_download() {
         return None;
     }
@@ -280,7 +334,19 @@ fn get_cached_icon(path: &str) -> Option<Vec<u8>> {
         let mut buffer = Vec::new();
 
         if f.read_to_end(&mut buffer).is_ok() {
-            return Some(buffer);
+            let size = buffer.len() as u64;
+            match storage::decrypt_cache_data(buffer) {
+                // Icons cached before they were validated could be anything, download them again
+                Ok(icon) if IconFormat::detect(&icon).is_none() => {
+                    CACHE_INDEX.lock().unwrap().remove(path);
+                    remove_file(path).ok();
+                }
+                Ok(icon) => {
+                    CACHE_INDEX.lock().unwrap().touch(path, size);
+                    return Some(icon);
+                }
+                Err(e) => warn!("Could not decrypt cached icon {:?}: {:?}", path, e),
+            }
         }
     }
 
@@ -599,47 +665,177 @@ fn download_icon(domain: &str) -> Result<Vec<u8>, Error> {
 
     let icon_result = get_icon_url(&domain)?;
 
-    let mut buffer = Vec::new();
-
     use data_url::DataUrl;
 
     for icon in icon_result.iconlist.iter().take(5) {
-        if icon.href.starts_with("data:image") {
+        let buffer = if icon.href.starts_with("data:image") {
             let datauri = DataUrl::process(&icon.href).unwrap();
             // Check if we are able to decode the data uri
             match datauri.decode_to_vec() {
-                Ok((body, _fragment)) => {
-                    // Also check if the size is atleast 67 bytes, which seems to be the smallest png i could create
-                    if body.len() >= 67 {
-                        buffer = body;
-                        break;
-                    }
+                Ok((body, _fragment)) => body,
+                _ => {
+                    warn!("data uri is invalid");
+                    continue;
                 }
-                _ => warn!("data uri is invalid"),
-            };
+            }
         } else {
             match get_page_with_cookies(&icon.href, &icon_result.cookies, &icon_result.referer) {
-                Ok(mut res) => {
+                Ok(res) => {
                     info!("Downloaded icon from {}", icon.href);
-                    res.copy_to(&mut buffer)?;
-                    break;
-                },
-                _ => warn!("Download failed for {}", icon.href),
-            };
+                    let mut buffer = Vec::new();
+                    res.take(MAX_ICON_DOWNLOAD_SIZE + 1).read_to_end(&mut buffer)?;
+                    if buffer.len() as u64 > MAX_ICON_DOWNLOAD_SIZE {
+                        warn!("Icon {} is too large", icon.href);
+                        continue;
+                    }
+                    buffer
+                }
+                _ => {
+                    warn!("Download failed for {}", icon.href);
+                    continue;
+                }
+            }
+        };
+
+        // Try the next icon when this one is not a valid image
+        match normalize_icon(buffer) {
+            Ok(icon) => return Ok(icon),
+            Err(e) => warn!("Invalid icon {}: {:?}", icon.href, e),
+        }
+    }
+
+    err!("No valid icon found")
+}
+
+//
+// Icon validation
+//
+// The sites can return anything from their icon links, like HTML error pages or huge images. The downloaded icons
+// must be one of the supported image formats, and the larger ones are downscaled and converted to PNG before
+// they are cached.
+const MAX_ICON_DOWNLOAD_SIZE: u64 = 5 * 1024 * 1024;
+// SVG icons are not resized, so they have a lower limit
+const MAX_SVG_ICON_SIZE: usize = 256 * 1024;
+const MAX_ICON_DIMENSION: u32 = 128;
+// Avoid decoding images that would use a lot of memory, even if the file is small
+const MAX_DECODED_ICON_PIXELS: u64 = 4096 * 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IconFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Ico,
+    Bmp,
+    Webp,
+    Svg,
+}
+
+impl IconFormat {
+    /// Detects the format of the image from its magic bytes
+    fn detect(data: &[u8]) -> Option<Self> {
+        match data {
+            [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(Self::Png),
+            [0xFF, 0xD8, 0xFF, ..] => Some(Self::Jpeg),
+            [b'G', b'I', b'F', b'8', b'7', b'a', ..] | [b'G', b'I', b'F', b'8', b'9', b'a', ..] => Some(Self::Gif),
+            [0x00, 0x00, 0x01, 0x00, ..] => Some(Self::Ico),
+            [b'B', b'M', ..] => Some(Self::Bmp),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(Self::Webp),
+            _ if is_svg(data) => Some(Self::Svg),
+            _ => None,
         }
     }
 
-    if buffer.is_empty() {
-        err!("Empty response")
+    fn content_type(self) -> ContentType {
+        match self {
+            Self::Png => ContentType::PNG,
+            Self::Jpeg => ContentType::JPEG,
+            Self::Gif => ContentType::GIF,
+            Self::Ico => ContentType::new("image", "x-icon"),
+            Self::Bmp => ContentType::BMP,
+            Self::Webp => ContentType::WEBP,
+            Self::Svg => ContentType::SVG,
+        }
     }
 
+    fn image_format(self) -> Option<image::ImageFormat> {
+        match self {
+            Self::Png => Some(image::ImageFormat::Png),
+            Self::Jpeg => Some(image::ImageFormat::Jpeg),
+            Self::Gif => Some(image::ImageFormat::Gif),
+            Self::Ico => Some(image::ImageFormat::Ico),
+            Self::Bmp => Some(image::ImageFormat::Bmp),
+            Self::Webp => Some(image::ImageFormat::WebP),
+            Self::Svg => None,
+        }
+    }
+}
+
+fn is_svg(data: &[u8]) -> bool {
+    // Only look at the start of the file, the root element should be there
+    let start = &data[..data.len().min(1024)];
+    let start = String::from_utf8_lossy(start);
+    let start = start.trim_start_matches('\u{feff}').trim_start();
+
+    (start.starts_with("<?xml") || start.starts_with("<svg") || start.starts_with("<!--")) && start.contains("<svg")
+}
+
+/// Checks that the icon is a supported image, and downscales it if it's too large
+fn normalize_icon(data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let format = match IconFormat::detect(&data) {
+        Some(format) => format,
+        None => err!("The icon is not a supported image"),
+    };
+
+    let image_format = match format.image_format() {
+        Some(image_format) => image_format,
+        None if data.len() <= MAX_SVG_ICON_SIZE => return Ok(data),
+        None => err!("The SVG icon is too large"),
+    };
+
+    let (width, height) = image::io::Reader::with_format(std::io::Cursor::new(&data), image_format).into_dimensions()?;
+    if u64::from(width) * u64::from(height) > MAX_DECODED_ICON_PIXELS {
+        err!("The icon dimensions are too large", format!("{}x{}", width, height))
+    }
+
+    // Bitmaps are not compressed, so they are always converted
+    if width <= MAX_ICON_DIMENSION && height <= MAX_ICON_DIMENSION && format != IconFormat::Bmp {
+        return Ok(data);
+    }
+
+    // Decoding also validates the whole image, not only the header
+    let image = image::load_from_memory_with_format(&data, image_format)?;
+    let image = image.thumbnail(MAX_ICON_DIMENSION, MAX_ICON_DIMENSION);
+
+    let mut buffer = Vec::new();
+    image.write_to(&mut buffer, image::ImageOutputFormat::Png)?;
     Ok(buffer)
 }
 
 fn save_icon(path: &str, icon: &[u8]) {
+    // The negative cache markers are empty, there is nothing to encrypt
+    let data = if icon.is_empty() {
+        Vec::new()
+    } else {
+        match storage::encrypt_cache_data(icon) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Error encrypting icon: {:?}", e);
+                return;
+            }
+        }
+    };
+
     match File::create(path) {
         Ok(mut f) => {
-            f.write_all(icon).expect("Error writing icon file");
+            f.write_all(&data).expect("Error writing icon file");
+
+            // The negative cache markers are removed by the periodic sweep instead
+            if !data.is_empty() {
+                let mut index = CACHE_INDEX.lock().unwrap();
+                index.insert(path, data.len() as u64);
+                enforce_cache_limits(&mut index);
+            }
         }
         Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
             create_dir_all(&CONFIG.icon_cache_folder()).expect("Error creating icon cache");
@@ -649,3 +845,440 @@ fn save_icon(path: &str, icon: &[u8]) {
         }
     }
 }
+
+//
+// Icon cache limits
+//
+// Keeps track of the size and the last use of the cached icons, to remove the least recently used ones when the cache
+// grows over `ICON_CACHE_MAX_SIZE` or `ICON_CACHE_MAX_FILES`. The index is built from the cache folder on first use,
+// taking the modification time of the files as their last use.
+struct CacheEntry {
+    size: u64,
+    last_used: SystemTime,
+}
+
+#[derive(Default)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    total_size: u64,
+}
+
+static CACHE_INDEX: Lazy<Mutex<CacheIndex>> = Lazy::new(|| Mutex::new(CacheIndex::load(&CONFIG.icon_cache_folder())));
+
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static CACHE_NEGATIVE_HITS: AtomicU64 = AtomicU64::new(0);
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+static CACHE_EVICTIONS: AtomicU64 = AtomicU64::new(0);
+
+// Used to check again when the sweep is disabled, in case it's enabled from the admin page
+const SWEEP_DISABLED_INTERVAL: Duration = Duration::from_secs(60);
+
+impl CacheIndex {
+    fn load(folder: &str) -> Self {
+        let mut index = Self::default();
+
+        let entries = match read_dir(folder) {
+            Ok(entries) => entries,
+            Err(_) => return index, // The folder is created with the first icon
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".miss") {
+                continue;
+            }
+
+            if let Ok(meta) = entry.metadata() {
+                if meta.is_file() {
+                    let last_used = meta.modified().unwrap_or_else(|_| SystemTime::now());
+                    index.add(format!("{}/{}", folder, name), meta.len(), last_used);
+                }
+            }
+        }
+
+        index
+    }
+
+    fn add(&mut self, path: String, size: u64, last_used: SystemTime) {
+        if let Some(old) = self.entries.insert(path, CacheEntry { size, last_used }) {
+            self.total_size -= old.size;
+        }
+        self.total_size += size;
+    }
+
+    fn insert(&mut self, path: &str, size: u64) {
+        self.add(path.to_string(), size, SystemTime::now());
+    }
+
+    fn touch(&mut self, path: &str, size: u64) {
+        match self.entries.get_mut(path) {
+            Some(entry) => entry.last_used = SystemTime::now(),
+            None => self.insert(path, size),
+        }
+    }
+
+    fn remove(&mut self, path: &str) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.total_size -= entry.size;
+        }
+    }
+
+    fn within_limits(&self, max_size: u64, max_files: u64) -> bool {
+        (max_size == 0 || self.total_size <= max_size) && (max_files == 0 || self.entries.len() as u64 <= max_files)
+    }
+
+    /// Removes the least recently used entries until the limits are met, returning their paths
+    fn evict(&mut self, max_size: u64, max_files: u64) -> Vec<String> {
+        if self.within_limits(max_size, max_files) {
+            return Vec::new();
+        }
+
+        let mut by_use: Vec<(SystemTime, String)> =
+            self.entries.iter().map(|(path, entry)| (entry.last_used, path.clone())).collect();
+        by_use.sort();
+
+        let mut evicted = Vec::new();
+        for (_, path) in by_use {
+            if self.within_limits(max_size, max_files) {
+                break;
+            }
+            self.remove(&path);
+            evicted.push(path);
+        }
+        evicted
+    }
+}
+
+fn enforce_cache_limits(index: &mut CacheIndex) {
+    let max_size = CONFIG.icon_cache_max_size() * 1024 * 1024;
+    let max_files = CONFIG.icon_cache_max_files();
+
+    for path in index.evict(max_size, max_files) {
+        match remove_file(&path) {
+            Ok(()) => {
+                CACHE_EVICTIONS.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => error!("Could not remove cached icon {:?}: {:?}", path, e),
+        }
+    }
+}
+
+/// Starts the thread that periodically removes the expired icons and negative cache markers
+pub fn start_cache_sweeper() {
+    // The cache is not used with an external icon service
+    if !CONFIG._icon_service_url().is_empty() {
+        return;
+    }
+
+    thread::Builder::new()
+        .name("icon-cache-sweeper".to_string())
+        .spawn(|| loop {
+            match CONFIG.icon_cache_sweep_interval() {
+                0 => thread::sleep(SWEEP_DISABLED_INTERVAL),
+                interval => {
+                    thread::sleep(Duration::from_secs(interval));
+                    sweep_cache();
+                }
+            }
+        })
+        .expect("Error starting icon cache sweeper");
+}
+
+fn sweep_cache() {
+    let folder = CONFIG.icon_cache_folder();
+    let entries = match read_dir(&folder) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    // The folder is read without holding the lock, so the icon requests are not blocked meanwhile
+    let mut removed_icons = Vec::new();
+    let mut removed_markers = 0;
+
+    for entry in entries.flatten() {
+        if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+            continue;
+        }
+
+        let path = format!("{}/{}", folder, entry.file_name().to_string_lossy());
+        if path.ends_with(".miss") {
+            if file_is_expired(&path, CONFIG.icon_cache_negttl()).unwrap_or(false) && remove_file(&path).is_ok() {
+                removed_markers += 1;
+            }
+        } else if file_is_expired(&path, CONFIG.icon_cache_ttl()).unwrap_or(false) && remove_file(&path).is_ok() {
+            removed_icons.push(path);
+        }
+    }
+
+    let mut index = CACHE_INDEX.lock().unwrap();
+    for path in &removed_icons {
+        index.remove(path);
+    }
+    enforce_cache_limits(&mut index);
+    drop(index);
+
+    if !removed_icons.is_empty() || removed_markers > 0 {
+        info!("Removed {} expired icons and {} negative cache markers", removed_icons.len(), removed_markers);
+    }
+}
+
+/// Returns the icon cache statistics shown in the admin diagnostics page
+pub fn cache_stats() -> Value {
+    let index = CACHE_INDEX.lock().unwrap();
+    let hits = CACHE_HITS.load(Ordering::Relaxed);
+    let negative_hits = CACHE_NEGATIVE_HITS.load(Ordering::Relaxed);
+    let misses = CACHE_MISSES.load(Ordering::Relaxed);
+
+    let requests = hits + negative_hits + misses;
+    let hit_ratio = if requests > 0 {
+        format!("{:.1}%", (hits + negative_hits) as f64 * 100.0 / requests as f64)
+    } else {
+        "-".to_string()
+    };
+
+    json!({
+        "enabled": CONFIG._icon_service_url().is_empty(),
+        "hits": hits,
+        "negative_hits": negative_hits,
+        "misses": misses,
+        "hit_ratio": hit_ratio,
+        "evictions": CACHE_EVICTIONS.load(Ordering::Relaxed),
+        "files": index.entries.len(),
+        "size": get_display_size(index.total_size as i64),
+        "max_files": CONFIG.icon_cache_max_files(),
+        "max_size": CONFIG.icon_cache_max_size(),
+    })
+}
+
+//
+// Fallback icons
+//
+// Generated with the first letter of the domain over a background color chosen from a hash of it, so the same
+// domain always gets the same icon. The PNG version uses a small bitmap font, to avoid depending on any font files.
+const FALLBACK_ICON_SIZE: usize = 64;
+const FALLBACK_GLYPH_SCALE: usize = 6;
+
+const FALLBACK_COLORS: &[[u8; 3]] = &[
+    [0x17, 0x5d, 0xdc],
+    [0x0e, 0x7c, 0x86],
+    [0x1b, 0x8a, 0x3a],
+    [0x5b, 0x8c, 0x00],
+    [0xb0, 0x6a, 0x00],
+    [0xc2, 0x41, 0x0c],
+    [0xc0, 0x26, 0x26],
+    [0xbe, 0x18, 0x5d],
+    [0x86, 0x19, 0x8f],
+    [0x5b, 0x21, 0xb6],
+    [0x37, 0x30, 0xa3],
+    [0x47, 0x55, 0x69],
+];
+
+// 5x7 glyphs, each row uses the 5 lowest bits with the most significant one on the left
+const FALLBACK_GLYPHS: &[(char, [u8; 7])] = &[
+    ('A', [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('B', [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E]),
+    ('C', [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E]),
+    ('D', [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E]),
+    ('E', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F]),
+    ('F', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10]),
+    ('G', [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F]),
+    ('H', [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('I', [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('J', [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C]),
+    ('K', [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11]),
+    ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F]),
+    ('M', [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11]),
+    ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+    ('O', [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('P', [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10]),
+    ('Q', [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D]),
+    ('R', [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11]),
+    ('S', [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E]),
+    ('T', [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+    ('U', [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('V', [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04]),
+    ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A]),
+    ('X', [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11]),
+    ('Y', [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04]),
+    ('Z', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F]),
+    ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+    ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+    ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+    ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+    ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+    ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+    ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+    ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+];
+
+// Used for the domains that don't start with a letter or number
+const FALLBACK_UNKNOWN_GLYPH: [u8; 7] = [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04];
+
+fn fallback_icon(domain: &str) -> Option<(ContentType, Vec<u8>)> {
+    let letter = fallback_letter(domain);
+    let color = fallback_color(domain);
+
+    match CONFIG.icon_fallback().as_str() {
+        "svg" => Some((ContentType::SVG, fallback_svg(letter, color).into_bytes())),
+        "png" => match fallback_png(letter, color) {
+            Ok(png) => Some((ContentType::PNG, png)),
+            Err(e) => {
+                error!("Error generating fallback icon: {:?}", e);
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+fn fallback_letter(domain: &str) -> char {
+    let name = domain.strip_prefix("www.").unwrap_or(domain);
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+        _ => '?',
+    }
+}
+
+fn fallback_color(domain: &str) -> [u8; 3] {
+    let hash = ring::digest::digest(&ring::digest::SHA256, domain.to_lowercase().as_bytes());
+    FALLBACK_COLORS[hash.as_ref()[0] as usize % FALLBACK_COLORS.len()]
+}
+
+fn fallback_svg(letter: char, color: [u8; 3]) -> String {
+    format!(
+        concat!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="0 0 {size} {size}">"##,
+            r##"<rect width="{size}" height="{size}" rx="8" fill="#{r:02x}{g:02x}{b:02x}"/>"##,
+            r##"<text x="50%" y="50%" dy=".35em" text-anchor="middle" font-family="Helvetica, Arial, sans-serif" "##,
+            r##"font-size="36" font-weight="bold" fill="#ffffff">{letter}</text></svg>"##
+        ),
+        size = FALLBACK_ICON_SIZE,
+        r = color[0],
+        g = color[1],
+        b = color[2],
+        letter = letter,
+    )
+}
+
+fn fallback_png(letter: char, color: [u8; 3]) -> Result<Vec<u8>, Error> {
+    let glyph = FALLBACK_GLYPHS
+        .iter()
+        .find(|(c, _)| *c == letter)
+        .map_or(FALLBACK_UNKNOWN_GLYPH, |(_, glyph)| *glyph);
+
+    // Centered glyph, scaled up
+    let glyph_width = 5 * FALLBACK_GLYPH_SCALE;
+    let glyph_height = 7 * FALLBACK_GLYPH_SCALE;
+    let left = (FALLBACK_ICON_SIZE - glyph_width) / 2;
+    let top = (FALLBACK_ICON_SIZE - glyph_height) / 2;
+
+    let size = FALLBACK_ICON_SIZE as u32;
+    let image = image::RgbImage::from_fn(size, size, |x, y| {
+        let (x, y) = (x as usize, y as usize);
+        let in_glyph = x >= left && x < left + glyph_width && y >= top && y < top + glyph_height;
+        let set = in_glyph && {
+            let row = glyph[(y - top) / FALLBACK_GLYPH_SCALE];
+            let column = (x - left) / FALLBACK_GLYPH_SCALE;
+            row & (0x10 >> column) != 0
+        };
+
+        if set {
+            image::Rgb([0xff, 0xff, 0xff])
+        } else {
+            image::Rgb(color)
+        }
+    });
+
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(image).write_to(&mut png, image::ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod fallback_tests {
+    use super::*;
+
+    #[test]
+    fn test_fallback_letter() {
+        assert_eq!(fallback_letter("www.example.com"), 'E');
+        assert_eq!(fallback_letter("github.com"), 'G');
+        assert_eq!(fallback_letter("1password.com"), '1');
+        assert_eq!(fallback_letter("_dmarc.example.com"), '?');
+    }
+
+    #[test]
+    fn test_fallback_png() {
+        let color = fallback_color("example.com");
+        assert_eq!(color, fallback_color("EXAMPLE.com"));
+
+        let png = fallback_png('E', color).unwrap();
+        assert_eq!(IconFormat::detect(&png), Some(IconFormat::Png));
+        let image = image::load_from_memory(&png).unwrap().to_rgb8();
+        assert_eq!(image.dimensions(), (FALLBACK_ICON_SIZE as u32, FALLBACK_ICON_SIZE as u32));
+        assert_eq!(image.get_pixel(0, 0).0, color);
+
+        // The top left corner of the 'E' is set
+        let (left, top) = ((FALLBACK_ICON_SIZE - 30) / 2, (FALLBACK_ICON_SIZE - 42) / 2);
+        assert_eq!(image.get_pixel(left as u32, top as u32).0, [0xff, 0xff, 0xff]);
+    }
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_eviction() {
+        let now = SystemTime::now();
+        let mut index = CacheIndex::default();
+        index.add("a.png".to_string(), 100, now - Duration::from_secs(30));
+        index.add("b.png".to_string(), 100, now - Duration::from_secs(10));
+        index.add("c.png".to_string(), 100, now - Duration::from_secs(20));
+        assert!(index.evict(0, 0).is_empty());
+
+        // Using an icon makes it the most recent one
+        index.touch("a.png", 100);
+        assert_eq!(index.evict(250, 0), vec!["c.png".to_string()]);
+        assert_eq!(index.total_size, 200);
+
+        assert_eq!(index.evict(0, 1), vec!["b.png".to_string()]);
+        assert_eq!(index.entries.len(), 1);
+        assert!(index.entries.contains_key("a.png"));
+    }
+}
+
+#[cfg(test)]
+mod normalize_tests {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(IconFormat::detect(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some(IconFormat::Png));
+        assert_eq!(IconFormat::detect(b"\0\0\x01\0\x01\0"), Some(IconFormat::Ico));
+        assert_eq!(IconFormat::detect(b"RIFF\0\0\0\0WEBPVP8 "), Some(IconFormat::Webp));
+        assert_eq!(IconFormat::detect(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"\"/>"), Some(IconFormat::Svg));
+        assert_eq!(IconFormat::detect(b"<!DOCTYPE html><html><body>Not Found</body></html>"), None);
+        assert_eq!(IconFormat::detect(b""), None);
+    }
+
+    #[test]
+    fn test_normalize_icon() {
+        assert!(normalize_icon(b"<html></html>".to_vec()).is_err());
+
+        // Small icons are kept as they are
+        let small = fallback_png('A', [0, 0, 0]).unwrap();
+        assert_eq!(normalize_icon(small.clone()).unwrap(), small);
+
+        // Large icons are downscaled and converted to PNG
+        let mut large = Vec::new();
+        image::DynamicImage::new_rgb8(512, 256).write_to(&mut large, image::ImageOutputFormat::Png).unwrap();
+        let normalized = normalize_icon(large).unwrap();
+        assert_eq!(IconFormat::detect(&normalized), Some(IconFormat::Png));
+        let image = image::load_from_memory(&normalized).unwrap();
+        assert_eq!((image.width(), image.height()), (MAX_ICON_DIMENSION, MAX_ICON_DIMENSION / 2));
+    }
+}
@@ -1,3 +1,5 @@
+use std::{thread, time::Duration};
+
 use chrono::Local;
 use num_traits::FromPrimitive;
 use rocket::{
@@ -12,14 +14,20 @@ use crate::{
         core::two_factor::{duo, email, email::EmailTokenData, yubikey},
         ApiResult, EmptyResult, JsonResult,
     },
-    auth::ClientIp,
-    db::{models::*, DbConn},
+    auth::{self, ClientIp},
+    db::{models::*, DbConn, DbPool},
     error::MapResult,
     mail, util, CONFIG,
 };
 
 pub fn routes() -> Vec<Route> {
-    routes![login]
+    routes![login, jwks]
+}
+
+/// The public keys used to verify the tokens, including the retired keys still in their grace period
+#[get("/.well-known/jwks")]
+fn jwks() -> Json<Value> {
+    Json(auth::jwks())
 }
 
 #[post("/connect/token", data = "<data>")]
@@ -29,7 +37,7 @@ fn login(data: Form<ConnectData>, conn: DbConn, ip: ClientIp) -> JsonResult {
     match data.grant_type.as_ref() {
         "refresh_token" => {
             _check_is_some(&data.refresh_token, "refresh_token cannot be blank")?;
-            _refresh_login(data, conn)
+            _refresh_login(data, conn, &ip)
         }
         "password" => {
             _check_is_some(&data.client_id, "client_id cannot be blank")?;
@@ -47,12 +55,47 @@ fn login(data: Form<ConnectData>, conn: DbConn, ip: ClientIp) -> JsonResult {
     }
 }
 
-fn _refresh_login(data: ConnectData, conn: DbConn) -> JsonResult {
+fn _refresh_login(data: ConnectData, conn: DbConn, ip: &ClientIp) -> JsonResult {
     // Extract token
     let token = data.refresh_token.unwrap();
 
+    // Revoked devices have an empty refresh token, never match those
+    if token.is_empty() {
+        err!("Invalid refresh token")
+    }
+
     // Get device by refresh token
-    let mut device = Device::find_by_refresh_token(&token, &conn).map_res("Invalid refresh token")?;
+    let mut device = match Device::find_by_refresh_token(&token, &conn) {
+        Some(device) => device,
+        None => {
+            if let Some(mut device) = Device::find_by_previous_refresh_token(&token, &conn) {
+                // The token was rotated just now, most likely by a concurrent refresh of the same client.
+                // Give it a new access token, but not the current refresh token, only the one it already has
+                if device.is_previous_token_reusable() && !device.is_session_expired() {
+                    let user = User::find_by_uuid(&device.user_uuid, &conn).unwrap();
+                    let orgs = UserOrganization::find_by_user(&user.uuid, &conn);
+                    let (access_token, expires_in) = device.access_token(&user, orgs);
+                    return Ok(Json(refresh_response(&user, &token, access_token, expires_in)));
+                }
+
+                // Otherwise either the token was stolen or this is the client of a stolen token.
+                // We can't tell which one, so log out the device and make both of them log in again
+                device.revoke();
+                device.save(&conn)?;
+                err!(
+                    "Invalid refresh token",
+                    format!("Reused refresh token, device {} logged out. IP: {}", device.uuid, ip.ip)
+                )
+            }
+            err!("Invalid refresh token")
+        }
+    };
+
+    if device.is_session_expired() {
+        device.revoke();
+        device.save(&conn)?;
+        err!("Session expired, log in again")
+    }
 
     // COMMON
     let user = User::find_by_uuid(&device.user_uuid, &conn).unwrap();
@@ -61,19 +104,25 @@ fn _refresh_login(data: ConnectData, conn: DbConn) -> JsonResult {
     let (access_token, expires_in) = device.refresh_tokens(&user, orgs);
 
     device.save(&conn)?;
-    Ok(Json(json!({
+    Ok(Json(refresh_response(&user, &device.refresh_token, access_token, expires_in)))
+}
+
+fn refresh_response(user: &User, refresh_token: &str, access_token: String, expires_in: i64) -> Value {
+    json!({
         "access_token": access_token,
         "expires_in": expires_in,
         "token_type": "Bearer",
-        "refresh_token": device.refresh_token,
+        "refresh_token": refresh_token,
         "Key": user.akey,
         "PrivateKey": user.private_key,
 
         "Kdf": user.client_kdf_type,
         "KdfIterations": user.client_kdf_iter,
+        "KdfMemory": user.client_kdf_memory,
+        "KdfParallelism": user.client_kdf_parallelism,
         "ResetMasterPassword": false, // TODO: according to official server seems something like: user.password_hash.is_empty(), but would need testing
         "scope": "api offline_access"
-    })))
+    })
 }
 
 fn _password_login(data: ConnectData, conn: DbConn, ip: &ClientIp) -> JsonResult {
@@ -87,15 +136,20 @@ fn _password_login(data: ConnectData, conn: DbConn, ip: &ClientIp) -> JsonResult
     let username = data.username.as_ref().unwrap();
     let user = match User::find_by_mail(username, &conn) {
         Some(user) => user,
-        None => err!(
-            "Username or password is incorrect. Try again",
-            format!("IP: {}. Username: {}.", ip.ip, username)
-        ),
+        None => {
+            // Otherwise the unknown emails would be rejected much faster than the wrong passwords
+            User::hash_dummy_password(data.password.as_ref().unwrap());
+            err!(
+                "Username or password is incorrect. Try again",
+                format!("IP: {}. Username: {}.", ip.ip, username)
+            )
+        }
     };
 
     // Check password
     let password = data.password.as_ref().unwrap();
     if !user.check_valid_password(password) {
+        save_login_history(&user.uuid, &data, ip, None, Some("Invalid master password"), &conn);
         err!(
             "Username or password is incorrect. Try again",
             format!("IP: {}. Username: {}.", ip.ip, username)
@@ -104,6 +158,7 @@ fn _password_login(data: ConnectData, conn: DbConn, ip: &ClientIp) -> JsonResult
 
     // Check if the user is disabled
     if !user.enabled {
+        save_login_history(&user.uuid, &data, ip, None, Some("User is disabled"), &conn);
         err!(
             "This user has been disabled",
             format!("IP: {}. Username: {}.", ip.ip, username)
@@ -127,13 +182,14 @@ fn _password_login(data: ConnectData, conn: DbConn, ip: &ClientIp) -> JsonResult
                     error!("Error updating user: {:#?}", e);
                 }
 
-                if let Err(e) = mail::send_verify_email(&user.email, &user.uuid) {
+                if let Err(e) = mail::send_verify_email(&user.email, &user.uuid, &user.culture) {
                     error!("Error auto-sending email verification email: {:#?}", e);
                 }
             }
         }
 
         // We still want the login to fail until they actually verified the email address
+        save_login_history(&user.uuid, &data, ip, None, Some("Email address not verified"), &conn);
         err!(
             "Please verify your email before trying again.",
             format!("IP: {}. Username: {}.", ip.ip, username)
@@ -142,13 +198,41 @@ fn _password_login(data: ConnectData, conn: DbConn, ip: &ClientIp) -> JsonResult
 
     let (mut device, new_device) = get_device(&data, &conn, &user);
 
-    let twofactor_token = twofactor_auth(&user.uuid, &data, &mut device, &ip, &conn)?;
+    let (twofactor_type, twofactor_token) = match twofactor_auth(&user.uuid, &data, &mut device, &ip, &conn) {
+        Ok(result) => result,
+        Err(e) => {
+            // Only count the attempts where a code was provided, the remember tokens can expire normally
+            if data.two_factor_token.is_some() && data.two_factor_provider != Some(TwoFactorType::Remember as i32) {
+                let reason = Some("Invalid two-step login code");
+                save_login_history(&user.uuid, &data, ip, data.two_factor_provider, reason, &conn);
+                twofactor_failed(user, ip, &conn);
+            }
+            return Err(e);
+        }
+    };
+
+    let mut user = user;
+    let reset_attempts = user.failed_twofactor_attempts > 0;
+    user.failed_twofactor_attempts = 0;
+
+    // Upgrade the password hash to the current settings, now that the password is known to be valid
+    let rehash = user.password_needs_rehash();
+    if rehash {
+        user.rehash_password(password);
+    }
+
+    if reset_attempts || rehash {
+        user.save(&conn)?;
+    }
 
     if CONFIG.mail_enabled() && new_device {
-        if let Err(e) = mail::send_new_device_logged_in(&user.email, &ip.ip.to_string(), &now, &device.name) {
+        if let Err(e) =
+            mail::send_new_device_logged_in(&user.email, &ip.ip.to_string(), &now, &device.name, &user.culture)
+        {
             error!("Error sending new device email: {:#?}", e);
 
             if CONFIG.require_device_email() {
+                save_login_history(&user.uuid, &data, ip, twofactor_type, Some("New device email failed"), &conn);
                 err!("Could not send login notification email. Please contact your administrator.")
             }
         }
@@ -157,6 +241,7 @@ fn _password_login(data: ConnectData, conn: DbConn, ip: &ClientIp) -> JsonResult
     // Common
     let orgs = UserOrganization::find_by_user(&user.uuid, &conn);
 
+    device.start_session();
     let (access_token, expires_in) = device.refresh_tokens(&user, orgs);
     device.save(&conn)?;
 
@@ -171,6 +256,8 @@ fn _password_login(data: ConnectData, conn: DbConn, ip: &ClientIp) -> JsonResult
         
         "Kdf": user.client_kdf_type,
         "KdfIterations": user.client_kdf_iter,
+        "KdfMemory": user.client_kdf_memory,
+        "KdfParallelism": user.client_kdf_parallelism,
         "ResetMasterPassword": false,// TODO: Same as above
         "scope": "api offline_access"
     });
@@ -179,10 +266,76 @@ fn _password_login(data: ConnectData, conn: DbConn, ip: &ClientIp) -> JsonResult
         result["TwoFactorToken"] = Value::String(token);
     }
 
+    save_login_history(&user.uuid, &data, ip, twofactor_type, None, &conn);
+
     info!("User {} logged in successfully. IP: {}", username, ip.ip);
     Ok(Json(result))
 }
 
+/// Counts the consecutive failed two-step login attempts, notifying the user when they reach the configured threshold.
+/// By then the master password was entered correctly several times, so the user should probably change it.
+fn twofactor_failed(mut user: User, ip: &ClientIp, conn: &DbConn) {
+    user.failed_twofactor_attempts += 1;
+    if let Err(e) = user.save(conn) {
+        error!("Error updating user: {:#?}", e);
+        return;
+    }
+
+    let threshold = CONFIG.security_notification_failed_2fa() as i32;
+    if threshold > 0 && user.failed_twofactor_attempts % threshold == 0 {
+        if let Err(e) = mail::send_failed_twofactor(&user, &ip.ip.to_string()) {
+            error!("Error sending failed two-step login notification: {:#?}", e);
+        }
+    }
+}
+
+// Interval between the removals of the login history entries older than `LOGIN_HISTORY_DAYS`
+const LOGIN_HISTORY_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Starts the thread that periodically removes the old login history entries
+pub fn start_login_history_purge(pool: DbPool) {
+    thread::Builder::new()
+        .name("login-history-purge".to_string())
+        .spawn(move || loop {
+            let days = CONFIG.login_history_days();
+            if days > 0 {
+                match pool.get() {
+                    Ok(conn) => {
+                        if let Err(e) = LoginHistory::delete_older_than(i64::from(days), &conn) {
+                            error!("Error deleting old login history: {:#?}", e);
+                        }
+                    }
+                    Err(e) => error!("Error deleting old login history: {:#?}", e),
+                }
+            }
+            thread::sleep(LOGIN_HISTORY_PURGE_INTERVAL);
+        })
+        .expect("Error starting login history purge");
+}
+
+/// Records a login attempt of the user, `failure_reason` is None when it succeeded.
+/// The refresh token grants are not recorded, the clients use them every time the access token expires
+fn save_login_history(
+    user_uuid: &str,
+    data: &ConnectData,
+    ip: &ClientIp,
+    twofactor_type: Option<i32>,
+    failure_reason: Option<&str>,
+    conn: &DbConn,
+) {
+    let device_type = util::try_parse_string(data.device_type.as_ref());
+    let mut entry =
+        LoginHistory::new(user_uuid, ip.ip.to_string(), &data.grant_type, device_type, data.device_name.clone());
+    entry.twofactor_type = twofactor_type;
+    if let Some(reason) = failure_reason {
+        entry = entry.failed(reason);
+    }
+
+    if let Err(e) = entry.save(conn) {
+        error!("Error saving login history: {:#?}", e);
+    }
+}
+
 /// Retrieves an existing device or creates a new device from ConnectData and the User
 fn get_device(data: &ConnectData, conn: &DbConn, user: &User) -> (Device, bool) {
     // On iOS, device_type sends "iOS", on others it sends a number
@@ -218,12 +371,12 @@ fn twofactor_auth(
     device: &mut Device,
     ip: &ClientIp,
     conn: &DbConn,
-) -> ApiResult<Option<String>> {
+) -> ApiResult<(Option<i32>, Option<String>)> {
     let twofactors = TwoFactor::find_by_user(user_uuid, conn);
 
     // No twofactor token if twofactor is disabled
     if twofactors.is_empty() {
-        return Ok(None);
+        return Ok((None, None));
     }
 
     let twofactor_ids: Vec<_> = twofactors.iter().map(|tf| tf.atype).collect();
@@ -263,10 +416,10 @@ fn twofactor_auth(
     }
 
     if !CONFIG.disable_2fa_remember() && remember == 1 {
-        Ok(Some(device.refresh_twofactor_remember()))
+        Ok((Some(selected_id), Some(device.refresh_twofactor_remember())))
     } else {
         device.delete_twofactor_remember();
-        Ok(None)
+        Ok((Some(selected_id), None))
     }
 }
 
@@ -11,8 +11,8 @@ use serde_json::Value;
 pub use crate::api::{
     admin::routes as admin_routes,
     core::routes as core_routes,
-    icons::routes as icons_routes,
-    identity::routes as identity_routes,
+    icons::{cache_stats as icon_cache_stats, routes as icons_routes, start_cache_sweeper as start_icon_cache_sweeper},
+    identity::{routes as identity_routes, start_login_history_purge},
     notifications::routes as notifications_routes,
     notifications::{start_notification_server, Notify, UpdateType},
     web::routes as web_routes,
@@ -66,7 +66,10 @@ use chashmap::CHashMap;
 use chrono::NaiveDateTime;
 use serde_json::from_str;
 
-use crate::db::models::{Cipher, Folder, User};
+use crate::db::{
+    models::{Cipher, Device, Folder, User},
+    DbPool,
+};
 
 use rmpv::Value;
 
@@ -123,7 +126,17 @@ fn convert_option<T: Into<Value>>(option: Option<T>) -> Value {
 pub struct WSHandler {
     out: Sender,
     user_uuid: Option<String>,
+    session: Option<WSSession>,
     users: WebSocketUsers,
+    pool: DbPool,
+}
+
+// The device and security stamp the connection was opened with,
+// used to close it once the access token is no longer valid
+struct WSSession {
+    device_uuid: String,
+    security_stamp: String,
+    expires_at: i64,
 }
 
 const RECORD_SEPARATOR: u8 = 0x1e;
@@ -138,6 +151,9 @@ struct InitialMessage {
 const PING_MS: u64 = 15_000;
 const PING: Token = Token(1);
 
+const REVALIDATE_MS: u64 = 60_000;
+const REVALIDATE: Token = Token(2);
+
 const ACCESS_TOKEN_KEY: &str = "access_token=";
 
 impl WSHandler {
@@ -174,6 +190,43 @@ impl WSHandler {
 
         None
     }
+
+    /// Checks that the token used to open the connection hasn't expired, and that
+    /// the user and device it was issued for are still valid with the same security stamp.
+    /// Returns the reason why the session is no longer valid, if any.
+    fn validate_session(&self) -> Option<&'static str> {
+        let (user_uuid, session) = match (&self.user_uuid, &self.session) {
+            (Some(user_uuid), Some(session)) => (user_uuid, session),
+            _ => return Some("Session not initialized"),
+        };
+
+        if chrono::Utc::now().timestamp() >= session.expires_at {
+            return Some("Access token expired");
+        }
+
+        let conn = match self.pool.get() {
+            Ok(conn) => conn,
+            Err(e) => {
+                // Don't drop the connection just because the database is temporarily unavailable
+                warn!("Error getting DB to validate the WebSocket session: {:?}", e);
+                return None;
+            }
+        };
+
+        match User::find_by_uuid(user_uuid, &conn) {
+            Some(user) if !user.enabled => return Some("User has been disabled"),
+            Some(user) if user.security_stamp != session.security_stamp => return Some("Security stamp has changed"),
+            Some(_) => (),
+            None => return Some("User no longer exists"),
+        }
+
+        match Device::find_by_uuid(&session.device_uuid, &conn) {
+            Some(device) if device.user_uuid != *user_uuid => Some("Device is no longer valid"),
+            Some(device) if device.is_revoked() => Some("Device has been logged out"),
+            Some(_) => None,
+            None => Some("Device is no longer valid"),
+        }
+    }
 }
 
 impl Handler for WSHandler {
@@ -199,6 +252,16 @@ impl Handler for WSHandler {
         // Assign the user to the handler
         let user_uuid = claims.sub;
         self.user_uuid = Some(user_uuid.clone());
+        self.session = Some(WSSession {
+            device_uuid: claims.device,
+            security_stamp: claims.sstamp,
+            expires_at: claims.exp,
+        });
+
+        // Make sure the user and device are still valid, the token alone doesn't guarantee that
+        if let Some(reason) = self.validate_session() {
+            return self.err(reason);
+        }
 
         // Add the current Sender to the user list
         let handler_insert = self.out.clone();
@@ -209,7 +272,10 @@ impl Handler for WSHandler {
             .upsert(user_uuid, || vec![handler_insert], |ref mut v| v.push(handler_update));
 
         // Schedule a ping to keep the connection alive
-        self.out.timeout(PING_MS, PING)
+        self.out.timeout(PING_MS, PING)?;
+
+        // Schedule the periodic session validation
+        self.out.timeout(REVALIDATE_MS, REVALIDATE)
     }
 
     fn on_message(&mut self, msg: Message) -> ws::Result<()> {
@@ -234,6 +300,14 @@ impl Handler for WSHandler {
 
             // reschedule the timeout
             self.out.timeout(PING_MS, PING)
+        } else if event == REVALIDATE {
+            if let Some(reason) = self.validate_session() {
+                info!("Closing WebSocket connection: {}", reason);
+                return self.out.close_with_reason(ws::CloseCode::Policy, reason);
+            }
+
+            // reschedule the timeout
+            self.out.timeout(REVALIDATE_MS, REVALIDATE)
         } else {
             Ok(())
         }
@@ -242,14 +316,16 @@ impl Handler for WSHandler {
 
 struct WSFactory {
     pub users: WebSocketUsers,
+    pool: DbPool,
 }
 
 impl WSFactory {
-    pub fn init() -> Self {
+    pub fn init(pool: DbPool) -> Self {
         WSFactory {
             users: WebSocketUsers {
                 map: Arc::new(CHashMap::new()),
             },
+            pool,
         }
     }
 }
@@ -261,7 +337,9 @@ impl Factory for WSFactory {
         WSHandler {
             out,
             user_uuid: None,
+            session: None,
             users: self.users.clone(),
+            pool: self.pool.clone(),
         }
     }
 
@@ -400,8 +478,8 @@ pub enum UpdateType {
 use rocket::State;
 pub type Notify<'a> = State<'a, WebSocketUsers>;
 
-pub fn start_notification_server() -> WebSocketUsers {
-    let factory = WSFactory::init();
+pub fn start_notification_server(pool: DbPool) -> WebSocketUsers {
+    let factory = WSFactory::init(pool);
     let users = factory.users.clone();
 
     if CONFIG.websocket_enabled() {
@@ -4,7 +4,13 @@ use rocket::{http::ContentType, response::content::Content, response::NamedFile,
 use rocket_contrib::json::Json;
 use serde_json::Value;
 
-use crate::{error::Error, util::Cached, CONFIG};
+use crate::{
+    auth::decode_file_download,
+    error::Error,
+    storage::{self, StoredObject},
+    util::Cached,
+    CONFIG,
+};
 
 pub fn routes() -> Vec<Route> {
     // If addding more routes here, consider also adding them to
@@ -55,9 +61,22 @@ fn web_files(p: PathBuf) -> Cached<Option<NamedFile>> {
     Cached::long(NamedFile::open(Path::new(&CONFIG.web_vault_folder()).join(p)).ok())
 }
 
-#[get("/attachments/<uuid>/<file..>")]
-fn attachments(uuid: String, file: PathBuf) -> Option<NamedFile> {
-    NamedFile::open(Path::new(&CONFIG.attachments_folder()).join(uuid).join(file)).ok()
+#[get("/attachments/<uuid>/<file_id>?<token>")]
+fn attachments(uuid: String, file_id: String, token: String) -> Option<StoredObject> {
+    // The token is generated when a client requests the attachment to download it, and is only valid for a few minutes
+    match decode_file_download(&token) {
+        Ok(claims) if claims.sub == uuid && claims.file_id == file_id => (),
+        _ => return None,
+    }
+
+    let key = format!("{}/{}", uuid, file_id);
+    match storage::attachments().open(&key) {
+        Ok(object) => object,
+        Err(e) => {
+            error!("Error opening attachment {}: {:?}", key, e);
+            None
+        }
+    }
 }
 
 #[get("/alive")]
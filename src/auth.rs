@@ -1,9 +1,19 @@
 //
 // JWT Handling
 //
+use std::{
+    fs,
+    path::Path,
+    time::{Duration as StdDuration, SystemTime},
+};
+
 use chrono::{Duration, Utc};
+use data_encoding::BASE64URL_NOPAD;
 use num_traits::FromPrimitive;
 use once_cell::sync::Lazy;
+use openssl::rsa::Rsa;
+use ring::digest::{digest, SHA256};
+use serde_json::Value;
 
 use jsonwebtoken::{self, Algorithm, DecodingKey, EncodingKey, Header};
 use serde::de::DeserializeOwned;
@@ -17,13 +27,19 @@ use crate::{
 
 const JWT_ALGORITHM: Algorithm = Algorithm::RS256;
 
-pub static DEFAULT_VALIDITY: Lazy<Duration> = Lazy::new(|| Duration::hours(2));
-static JWT_HEADER: Lazy<Header> = Lazy::new(|| Header::new(JWT_ALGORITHM));
+static JWT_HEADER: Lazy<Header> = Lazy::new(|| {
+    let mut header = Header::new(JWT_ALGORITHM);
+    header.kid = Some(JWT_KEY_ID.clone());
+    header
+});
 pub static JWT_LOGIN_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|login", CONFIG.domain_origin()));
 static JWT_INVITE_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|invite", CONFIG.domain_origin()));
 static JWT_DELETE_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|delete", CONFIG.domain_origin()));
 static JWT_VERIFYEMAIL_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|verifyemail", CONFIG.domain_origin()));
 static JWT_ADMIN_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|admin", CONFIG.domain_origin()));
+static JWT_FILE_UPLOAD_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|file_upload", CONFIG.domain_origin()));
+static JWT_FILE_DOWNLOAD_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|file_download", CONFIG.domain_origin()));
+static JWT_NOTIFICATIONS_ISSUER: Lazy<String> = Lazy::new(|| format!("{}|notifications", CONFIG.domain_origin()));
 static PRIVATE_RSA_KEY: Lazy<Vec<u8>> = Lazy::new(|| match read_file(&CONFIG.private_rsa_key()) {
     Ok(key) => key,
     Err(e) => panic!("Error loading private RSA Key.\n Error: {}", e),
@@ -32,6 +48,16 @@ static PUBLIC_RSA_KEY: Lazy<Vec<u8>> = Lazy::new(|| match read_file(&CONFIG.publ
     Ok(key) => key,
     Err(e) => panic!("Error loading public RSA Key.\n Error: {}", e),
 });
+static JWT_KEY_ID: Lazy<String> = Lazy::new(|| match rsa_key_id(&PUBLIC_RSA_KEY) {
+    Ok(kid) => kid,
+    Err(e) => panic!("Error reading public RSA Key.\n Error: {:?}", e),
+});
+static RETIRED_RSA_KEYS: Lazy<Vec<RetiredKey>> = Lazy::new(|| RetiredKey::load_all(&CONFIG.retired_rsa_keys_folder()));
+
+/// Validity of the login access tokens, it can be changed from the admin page so it's not cached
+pub fn login_validity() -> Duration {
+    Duration::seconds(CONFIG.access_token_lifetime() as i64)
+}
 
 pub fn encode_jwt<T: Serialize>(claims: &T) -> String {
     match jsonwebtoken::encode(&JWT_HEADER, claims, &EncodingKey::from_rsa_der(&PRIVATE_RSA_KEY)) {
@@ -41,6 +67,13 @@ pub fn encode_jwt<T: Serialize>(claims: &T) -> String {
 }
 
 fn decode_jwt<T: DeserializeOwned>(token: &str, issuer: String) -> Result<T, Error> {
+    // The login tokens of a retired key are only needed until they expire, which limits the use of a leaked key
+    let grace_period = if issuer == *JWT_LOGIN_ISSUER {
+        CONFIG.jwt_key_grace_period().min(CONFIG.access_token_lifetime())
+    } else {
+        CONFIG.jwt_key_grace_period()
+    };
+
     let validation = jsonwebtoken::Validation {
         leeway: 30, // 30 seconds
         validate_exp: true,
@@ -52,10 +85,125 @@ fn decode_jwt<T: DeserializeOwned>(token: &str, issuer: String) -> Result<T, Err
     };
 
     let token = token.replace(char::is_whitespace, "");
+    let kid = jsonwebtoken::decode_header(&token).map_res("Error decoding JWT")?.kid;
+
+    // The tokens created before the key IDs were added don't have one, those are checked with all the keys
+    let mut last_error = None;
+    let keys = verification_keys(grace_period).filter(|&(key_id, _)| kid.is_none() || kid.as_deref() == Some(key_id));
+    for (_, key) in keys {
+        match jsonwebtoken::decode(&token, &DecodingKey::from_rsa_der(key), &validation) {
+            Ok(data) => return Ok(data.claims),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    match last_error {
+        Some(e) => Err(e).map_res("Error decoding JWT"),
+        None => err!("Error decoding JWT", format!("Unknown JWT key: {:?}", kid)),
+    }
+}
+
+/// The public part of a key that was replaced with `rotate-jwt-key`, unless it was revoked.
+/// The modification time of the file is used as the rotation date
+struct RetiredKey {
+    kid: String,
+    key: Vec<u8>,
+    retired_at: SystemTime,
+}
+
+impl RetiredKey {
+    fn load_all(folder: &str) -> Vec<Self> {
+        let entries = match fs::read_dir(folder) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(), // Nothing was rotated yet
+        };
+
+        entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let retired_at = path.metadata().and_then(|m| m.modified()).ok()?;
+                let key = read_file(path.to_str()?).ok()?;
+                match rsa_key_id(&key) {
+                    Ok(kid) => Some(Self {
+                        kid,
+                        key,
+                        retired_at,
+                    }),
+                    Err(e) => {
+                        warn!("Ignoring invalid retired JWT key {:?}: {:?}", path, e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn in_grace_period(&self, grace_period: u64) -> bool {
+        let grace_period = StdDuration::from_secs(grace_period);
+        self.retired_at.elapsed().map(|age| age < grace_period).unwrap_or(true)
+    }
+}
 
-    jsonwebtoken::decode(&token, &DecodingKey::from_rsa_der(&PUBLIC_RSA_KEY), &validation)
-        .map(|d| d.claims)
-        .map_res("Error decoding JWT")
+/// Returns the key ID and public key of the current key, followed by the retired keys still in their grace period
+fn verification_keys(grace_period: u64) -> impl Iterator<Item = (&'static str, &'static [u8])> {
+    let retired = RETIRED_RSA_KEYS
+        .iter()
+        .filter(move |k| k.in_grace_period(grace_period))
+        .map(|k| (k.kid.as_str(), k.key.as_slice()));
+
+    std::iter::once((JWT_KEY_ID.as_str(), PUBLIC_RSA_KEY.as_slice())).chain(retired)
+}
+
+/// Returns the public key in JWK format, using its RFC 7638 thumbprint as the key ID
+fn rsa_public_jwk(public_key: &[u8]) -> Result<Value, Error> {
+    let rsa = Rsa::public_key_from_der_pkcs1(public_key)?;
+    let n = BASE64URL_NOPAD.encode(&rsa.n().to_vec());
+    let e = BASE64URL_NOPAD.encode(&rsa.e().to_vec());
+
+    // The thumbprint is computed over the required members in lexicographic order, without whitespace
+    let thumbprint_input = format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, e, n);
+    let kid = BASE64URL_NOPAD.encode(digest(&SHA256, thumbprint_input.as_bytes()).as_ref());
+
+    Ok(json!({
+        "kty": "RSA",
+        "use": "sig",
+        "alg": "RS256",
+        "kid": kid,
+        "n": n,
+        "e": e,
+    }))
+}
+
+fn rsa_key_id(public_key: &[u8]) -> Result<String, Error> {
+    let jwk = rsa_public_jwk(public_key)?;
+    Ok(jwk["kid"].as_str().unwrap_or_default().to_string())
+}
+
+/// The public keys that are currently accepted, in JWKS format
+pub fn jwks() -> Value {
+    let keys: Vec<Value> =
+        verification_keys(CONFIG.jwt_key_grace_period()).filter_map(|(_, key)| rsa_public_jwk(key).ok()).collect();
+    json!({ "keys": keys })
+}
+
+/// Moves the public part of the current key to the retired keys folder, so the tokens it signed are still accepted
+/// during the grace period once the new key is in use. The retired keys past their grace period are removed.
+/// Returns the ID of the retired key
+pub fn retire_current_rsa_key() -> Result<String, Error> {
+    let public_key = read_file(&CONFIG.public_rsa_key())?;
+    let kid = rsa_key_id(&public_key)?;
+
+    let folder = CONFIG.retired_rsa_keys_folder();
+    fs::create_dir_all(&folder)?;
+
+    for retired in RetiredKey::load_all(&folder) {
+        if !retired.in_grace_period(CONFIG.jwt_key_grace_period()) {
+            fs::remove_file(Path::new(&folder).join(format!("{}.pub.der", retired.kid)))?;
+        }
+    }
+
+    fs::write(Path::new(&folder).join(format!("{}.pub.der", kid)), &public_key)?;
+    Ok(kid)
 }
 
 pub fn decode_login(token: &str) -> Result<LoginJWTClaims, Error> {
@@ -78,6 +226,18 @@ pub fn decode_admin(token: &str) -> Result<AdminJWTClaims, Error> {
     decode_jwt(token, JWT_ADMIN_ISSUER.to_string())
 }
 
+pub fn decode_file_upload(token: &str) -> Result<FileUploadJWTClaims, Error> {
+    decode_jwt(token, JWT_FILE_UPLOAD_ISSUER.to_string())
+}
+
+pub fn decode_file_download(token: &str) -> Result<FileDownloadJWTClaims, Error> {
+    decode_jwt(token, JWT_FILE_DOWNLOAD_ISSUER.to_string())
+}
+
+pub fn decode_notifications(token: &str) -> Result<BasicJWTClaims, Error> {
+    decode_jwt(token, JWT_NOTIFICATIONS_ISSUER.to_string())
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginJWTClaims {
     // Not before
@@ -212,6 +372,81 @@ pub fn generate_admin_claims() -> AdminJWTClaims {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileUploadJWTClaims {
+    // Not before
+    pub nbf: i64,
+    // Expiration time
+    pub exp: i64,
+    // Issuer
+    pub iss: String,
+    // Subject
+    pub sub: String,
+
+    pub cipher_id: String,
+    pub attachment_id: String,
+}
+
+pub fn generate_file_upload_claims(user_uuid: String, cipher_id: String, attachment_id: String) -> FileUploadJWTClaims {
+    let time_now = Utc::now().naive_utc();
+    FileUploadJWTClaims {
+        nbf: time_now.timestamp(),
+        exp: (time_now + Duration::minutes(30)).timestamp(),
+        iss: JWT_FILE_UPLOAD_ISSUER.to_string(),
+        sub: user_uuid,
+        cipher_id,
+        attachment_id,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileDownloadJWTClaims {
+    // Not before
+    pub nbf: i64,
+    // Expiration time
+    pub exp: i64,
+    // Issuer
+    pub iss: String,
+    // Subject
+    pub sub: String,
+
+    pub file_id: String,
+}
+
+pub fn generate_file_download_claims(cipher_id: String, file_id: String) -> FileDownloadJWTClaims {
+    let time_now = Utc::now().naive_utc();
+    FileDownloadJWTClaims {
+        nbf: time_now.timestamp(),
+        exp: (time_now + Duration::minutes(5)).timestamp(),
+        iss: JWT_FILE_DOWNLOAD_ISSUER.to_string(),
+        sub: cipher_id,
+        file_id,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BasicJWTClaims {
+    // Not before
+    pub nbf: i64,
+    // Expiration time
+    pub exp: i64,
+    // Issuer
+    pub iss: String,
+    // Subject
+    pub sub: String,
+}
+
+/// Used in the links of the security notification emails, to let the users opt out without logging in
+pub fn generate_notifications_claims(user_uuid: String) -> BasicJWTClaims {
+    let time_now = Utc::now().naive_utc();
+    BasicJWTClaims {
+        nbf: time_now.timestamp(),
+        exp: (time_now + Duration::days(7)).timestamp(),
+        iss: JWT_NOTIFICATIONS_ISSUER.to_string(),
+        sub: user_uuid,
+    }
+}
+
 //
 // Bearer token authentication
 //
@@ -290,6 +525,11 @@ impl<'a, 'r> FromRequest<'a, 'r> for Headers {
             None => err_handler!("Invalid device id"),
         };
 
+        // Access tokens issued to a device stay valid until they expire, so check it wasn't logged out since
+        if device.is_revoked() {
+            err_handler!("Device has been logged out")
+        }
+
         let user = match User::find_by_uuid(&user_uuid, &conn) {
             Some(user) => user,
             None => err_handler!("Device has no user associated"),
@@ -32,6 +32,9 @@ const PRIVACY_CONFIG: &[&str] = &[
     "domain",
     "helo_name",
     "org_creation_users",
+    "s3_access_key",
+    "s3_bucket",
+    "s3_endpoint",
     "signups_domains_whitelist",
     "smtp_from",
     "smtp_host",
@@ -299,10 +302,16 @@ make_config! {
         icon_cache_folder:      String, false,  auto,   |c| format!("{}/{}", c.data_folder, "icon_cache");
         /// Attachments folder
         attachments_folder:     String, false,  auto,   |c| format!("{}/{}", c.data_folder, "attachments");
+        /// Temporary files folder
+        tmp_folder:             String, false,  auto,   |c| format!("{}/{}", c.data_folder, "tmp");
         /// Templates folder
         templates_folder:       String, false,  auto,   |c| format!("{}/{}", c.data_folder, "templates");
         /// Session JWT key
         rsa_key_filename:       String, false,  auto,   |c| format!("{}/{}", c.data_folder, "rsa_key");
+        /// Account enumeration protection key
+        enumeration_key_filename: String, false, auto,  |c| format!("{}/{}", c.data_folder, "enumeration_key");
+        /// HIBP cache key
+        hibp_cache_key_filename: String, false, auto,   |c| format!("{}/{}", c.data_folder, "hibp_cache_key");
         /// Web vault folder
         web_vault_folder:       String, false,  def,    "web-vault/".to_string();
     },
@@ -331,12 +340,24 @@ make_config! {
 
         /// HIBP Api Key |> HaveIBeenPwned API Key, request it here: https://haveibeenpwned.com/API/Key
         hibp_api_key:           Pass,   true,   option;
+        /// HIBP cache expiry |> Number of seconds to keep the HaveIBeenPwned breach lookups in the database (0 disables the cache)
+        hibp_cache_ttl:         u64,    true,   def,    86_400;
+        /// HIBP rate limit |> Maximum number of HaveIBeenPwned breach lookups per user and hour (0 is no limit)
+        hibp_rate_limit:        u32,    true,   def,    30;
+        /// HIBP password ranges folder |> Folder with the password hash ranges downloaded from HaveIBeenPwned, one <PREFIX>.txt file per prefix.
+        /// They are served in /api/hibp/range/<prefix> for installations without internet access
+        hibp_range_folder:      String, true,   option;
 
         /// Per-user attachment limit (KB) |> Limit in kilobytes for a users attachments, once the limit is exceeded it won't be possible to upload more
         user_attachment_limit:  i64,    true,   option;
         /// Per-organization attachment limit (KB) |> Limit in kilobytes for an organization attachments, once the limit is exceeded it won't be possible to upload more
         org_attachment_limit:   i64,    true,   option;
 
+        /// Login history retention (days) |> Number of days to keep the login history of the users, the older entries are removed every hour (0 keeps it forever)
+        login_history_days:     u32,    true,   def,    90;
+        /// Login history entries per user |> Maximum number of successful and of failed login history entries kept for each user (0 is no limit)
+        login_history_max_entries: u32, true,   def,    100;
+
         /// Disable icon downloads |> Set to true to disable icon downloading, this would still serve icons from
         /// $ICON_CACHE_FOLDER, but it won't produce any external network request. Needs to set $ICON_CACHE_TTL to 0,
         /// otherwise it will delete them and they won't be downloaded again.
@@ -356,12 +377,24 @@ make_config! {
         org_creation_users:     String, true,   def,    "".to_string();
         /// Allow invitations |> Controls whether users can be invited by organization admins, even when signups are otherwise disabled
         invitations_allowed:    bool,   true,   def,    true;
-        /// Password iterations |> Number of server-side passwords hashing iterations.
-        /// The changes only apply when a user changes their password. Not recommended to lower the value
+        /// Password hash algorithm |> Algorithm used to hash the passwords on the server: "argon2id" or "pbkdf2".
+        /// The existing passwords are rehashed with the current settings the next time the users log in
+        password_hash_algorithm: String, true,  def,    "argon2id".to_string();
+        /// Password iterations |> Number of server-side passwords hashing iterations when using PBKDF2.
+        /// Not recommended to lower the value, the existing passwords are only rehashed when it's increased
         password_iterations:    i32,    true,   def,    100_000;
+        /// Argon2 memory |> Memory used to hash the passwords with Argon2id, in KiB, up to 1 GiB (1048576)
+        password_argon2_memory: u32,    true,   def,    19_456;
+        /// Argon2 iterations |> Number of iterations used to hash the passwords with Argon2id
+        password_argon2_iterations: u32, true,  def,    2;
+        /// Argon2 parallelism |> Number of lanes used to hash the passwords with Argon2id
+        password_argon2_parallelism: u32, true, def,    1;
         /// Show password hints |> Controls if the password hint should be shown directly in the web page.
         /// Otherwise, if email is disabled, there is no way to see the password hint
         show_password_hint:     bool,   true,   def,    true;
+        /// Account lookups rate limit |> Maximum number of registrations and password hint requests per IP address and hour.
+        /// Limits how fast these can be used to find out which emails have an account (0 is no limit)
+        account_lookup_rate_limit: u32, true,   def,    30;
 
         /// Admin page token |> The token used to authenticate in this very same page. Changing it here won't deauthorize the current session
         admin_token:            Pass,   true,   option;
@@ -377,10 +410,27 @@ make_config! {
         ip_header:              String, true,   def,    "X-Real-IP".to_string();
         /// Internal IP header property, used to avoid recomputing each time
         _ip_header_enabled:     bool,   false,  gen,    |c| &c.ip_header.trim().to_lowercase() != "none";
+        /// Icon service |> The icon service used by the clients, one of: "internal" (downloads the icons from the websites),
+        /// "bitwarden", "duckduckgo", "google", or the URL template of a custom service with "{}" in place of the domain.
+        /// The external services are used by redirecting the clients to them
+        icon_service:           String, false,  def,    "internal".to_string();
+        /// Internal URL template of the external icon service, used to avoid recomputing each time
+        _icon_service_url:      String, false,  gen,    |c| generate_icon_service_url(&c.icon_service);
+        /// Icon redirect code |> The HTTP status code used to redirect to the external icon service: 301, 302, 307 or 308
+        icon_redirect_code:     u32,    true,   def,    302;
+        /// Fallback icons |> When no icon can be obtained, generate one with the first letter of the domain: "none", "png" or "svg".
+        /// Useful when the server can't reach the websites
+        icon_fallback:          String, true,   def,    "none".to_string();
         /// Positive icon cache expiry |> Number of seconds to consider that an already cached icon is fresh. After this period, the icon will be redownloaded
         icon_cache_ttl:         u64,    true,   def,    2_592_000;
         /// Negative icon cache expiry |> Number of seconds before trying to download an icon that failed again.
         icon_cache_negttl:      u64,    true,   def,    259_200;
+        /// Maximum icon cache size |> Maximum size of the icon cache in MiB. When exceeded, the least recently used icons are removed (0 is no limit)
+        icon_cache_max_size:    u64,    true,   def,    100;
+        /// Maximum cached icons |> Maximum number of icons kept in the cache. When exceeded, the least recently used icons are removed (0 is no limit)
+        icon_cache_max_files:   u64,    true,   def,    10_000;
+        /// Icon cache cleanup interval |> Number of seconds between the removal of the expired icons and negative cache markers (0 disables it)
+        icon_cache_sweep_interval: u64, true,   def,    3_600;
         /// Icon download timeout |> Number of seconds when to stop attempting to download an icon.
         icon_download_timeout:  u64,    true,   def,    10;
         /// Icon blacklist Regex |> Any domains or IPs that match this regex won't be fetched by the icon service.
@@ -394,6 +444,17 @@ make_config! {
         /// Note that the checkbox would still be present, but ignored.
         disable_2fa_remember:   bool,   true,   def,    false;
 
+        /// Access token lifetime |> Number of seconds the access tokens are valid, the clients get a new one with their refresh token when it expires
+        access_token_lifetime:  u64,    true,   def,    7_200;
+        /// Session idle lifetime |> Number of seconds a device can go without refreshing its access token before it has to log in again (0 is no limit)
+        session_idle_lifetime:  u64,    true,   def,    2_592_000;
+        /// Session absolute lifetime |> Number of seconds after logging in when a device has to log in again, even if it's in use (0 is no limit)
+        session_absolute_lifetime: u64, true,   def,    0;
+        /// JWT key grace period |> Number of seconds the tokens signed with a key are still accepted after rotating it
+        /// with `bitwarden_rs rotate-jwt-key`. The access tokens are only accepted until they expire, and the clients get new ones
+        /// with their refresh tokens either way. Use `rotate-jwt-key --revoke` to stop accepting a compromised key right away
+        jwt_key_grace_period:   u64,    true,   def,    604_800;
+
         /// Disable authenticator time drifted codes to be valid |> Enabling this only allows the current TOTP code to be valid
         /// TOTP codes of the previous and next 30 seconds will be invalid.
         authenticator_disable_time_drift: bool, true, def, false;
@@ -402,6 +463,13 @@ make_config! {
         /// If sending the email fails the login attempt will fail.
         require_device_email:   bool,   true,   def,     false;
 
+        /// Security notification emails |> Notify the users by email of sensitive changes to their account, like a new master password,
+        /// email or two-step login method, and of actions taken on it from the admin page. Users can opt out with the link in the emails
+        security_notifications: bool,   true,   def,     true;
+
+        /// Failed two-step login notification threshold |> Notify the user after this many consecutive failed two-step login attempts, 0 to disable
+        security_notification_failed_2fa: u32, true, def, 5;
+
         /// Reload templates (Dev) |> When this is set to true, the templates get reloaded with every request.
         /// ONLY use this during development, as it can slow down the server
         reload_templates:       bool,   true,   def,    false;
@@ -430,6 +498,29 @@ make_config! {
         allowed_iframe_ancestors: String, true, def,    String::new();
     },
 
+    /// Attachment storage settings
+    storage {
+        /// Attachments storage |> Where to store the attachments, one of: "filesystem" (uses $ATTACHMENTS_FOLDER) or "s3"
+        attachments_storage:    String, false,  def,    "filesystem".to_string();
+        /// S3 endpoint |> URL of the S3-compatible service, for example https://s3.eu-west-1.amazonaws.com or http://minio:9000
+        s3_endpoint:            String, false,  option;
+        /// S3 bucket
+        s3_bucket:              String, false,  option;
+        /// S3 region
+        s3_region:              String, false,  def,    "us-east-1".to_string();
+        /// S3 access key
+        s3_access_key:          String, false,  option;
+        /// S3 secret key
+        s3_secret_key:          Pass,   false,  option;
+        /// S3 path style |> Use path-style URLs (endpoint/bucket/key), needed by most self-hosted services. Otherwise virtual-hosted URLs (bucket.endpoint/key) are used
+        s3_path_style:          bool,   false,  def,    true;
+        /// S3 key prefix |> Prefix added to the keys of all the stored objects, for example "attachments/"
+        s3_key_prefix:          String, false,  def,    String::new();
+        /// Storage encryption key |> Secret used to encrypt the attachments and cached icons at rest, and to obfuscate their file names.
+        /// Changing or removing it makes the files stored while it was set unreadable
+        storage_encryption_key: Pass,   false,  option;
+    },
+
     /// Yubikey settings
     yubico: _enable_yubico {
         /// Enabled
@@ -460,6 +551,14 @@ make_config! {
     smtp: _enable_smtp {
         /// Enabled
         _enable_smtp:                  bool,   true,   def,     true;
+        /// Mail transport |> How the emails are sent: "smtp", "sendmail" (using a local MTA binary) or "file" (written as .eml files to $MAIL_FILE_FOLDER, useful for testing)
+        mail_transport:                String, true,   def,     "smtp".to_string();
+        /// Sendmail command |> Path of the sendmail compatible binary, used with the "sendmail" transport.
+        /// Not editable from the admin page, as the server executes it
+        sendmail_command:              String, false,  def,     "sendmail".to_string();
+        /// Mail file folder |> Folder where the emails are written, used with the "file" transport.
+        /// Not editable from the admin page, as the server writes files to it
+        mail_file_folder:              String, false,  auto,    |c| format!("{}/{}", c.data_folder, "mail");
         /// Host
         smtp_host:                     String, true,   option;
         /// Enable Secure SMTP |> (Explicit) - Enabling this by default would use STARTTLS (Standard ports 587 or 25)
@@ -480,6 +579,18 @@ make_config! {
         smtp_auth_mechanism:           String, true,   option;
         /// SMTP connection timeout |> Number of seconds when to stop trying to connect to the SMTP server
         smtp_timeout:                  u64,    true,   def,     15;
+        /// DKIM selector |> Selector of the DKIM key, the public key needs to be published in the DNS record <selector>._domainkey.<domain>
+        dkim_selector:                 String, true,   option;
+        /// DKIM domain |> Domain used to sign the emails, usually the domain of $SMTP_FROM
+        dkim_domain:                   String, true,   option;
+        /// DKIM private key file |> Path of the RSA private key in PEM format used to sign the emails
+        dkim_private_key_file:         String, true,   option;
+        /// Mail queue workers |> Number of background workers sending the queued emails
+        mail_queue_workers:            u32,    false,  def,     2;
+        /// Max delivery attempts |> Number of times to try sending an email before marking it as failed. The failed emails are listed in the diagnostics page, where they can be resent or dismissed
+        mail_max_attempts:             u32,    true,   def,     8;
+        /// Retry delay |> Number of seconds to wait before retrying to send an email, doubled after each failed attempt
+        mail_retry_delay:              u64,    true,   def,     60;
         /// Server name sent during HELO |> By default this value should be is on the machine's hostname, but might need to be changed in case it trips some anti-spam filters
         helo_name:                     String, true,   option;
         /// Enable SMTP debugging (Know the risks!) |> DANGEROUS: Enabling this will output very detailed SMTP messages. This could contain sensitive information like passwords and usernames! Only enable this during troubleshooting!
@@ -493,7 +604,7 @@ make_config! {
     /// Email 2FA Settings
     email_2fa: _enable_email_2fa {
         /// Enabled |> Disabling will prevent users from setting up new email 2FA and using existing email 2FA configured
-        _enable_email_2fa:      bool,   true,   auto,    |c| c._enable_smtp && c.smtp_host.is_some();
+        _enable_email_2fa:      bool,   true,   auto,    |c| c._enable_smtp && is_mail_configured(c);
         /// Email token size |> Number of digits in an email token (min: 6, max: 19). Note that the Bitwarden clients are hardcoded to mention 6 digit codes regardless of this setting.
         email_token_size:       u32,    true,   def,      6;
         /// Token expiration time |> Maximum time in seconds a token is valid. The time the user has to open email client and copy token.
@@ -553,11 +664,21 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
     }
 
     if cfg._enable_smtp {
-        if cfg.smtp_host.is_some() == cfg.smtp_from.is_empty() {
-            err!("Both `SMTP_HOST` and `SMTP_FROM` need to be set for email support")
+        match cfg.mail_transport.as_str() {
+            "smtp" => {
+                if cfg.smtp_host.is_some() == cfg.smtp_from.is_empty() {
+                    err!("Both `SMTP_HOST` and `SMTP_FROM` need to be set for email support")
+                }
+            }
+            "sendmail" | "file" => {
+                if cfg.smtp_from.is_empty() {
+                    err!("`SMTP_FROM` needs to be set for email support")
+                }
+            }
+            _ => err!("`MAIL_TRANSPORT` needs to be one of: smtp, sendmail, file"),
         }
 
-        if cfg.smtp_host.is_some() && !cfg.smtp_from.contains('@') {
+        if is_mail_configured(cfg) && !cfg.smtp_from.contains('@') {
             err!("SMTP_FROM does not contain a mandatory @ sign")
         }
 
@@ -565,7 +686,26 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
             err!("Both `SMTP_USERNAME` and `SMTP_PASSWORD` need to be set to enable email authentication")
         }
 
-        if cfg._enable_email_2fa && (!cfg._enable_smtp || cfg.smtp_host.is_none()) {
+        let dkim_options = [&cfg.dkim_selector, &cfg.dkim_domain, &cfg.dkim_private_key_file];
+        if dkim_options.iter().any(|o| o.is_some()) && !dkim_options.iter().all(|o| o.is_some()) {
+            err!("`DKIM_SELECTOR`, `DKIM_DOMAIN` and `DKIM_PRIVATE_KEY_FILE` need to be set to enable DKIM signing")
+        }
+
+        if let Some(ref key_file) = cfg.dkim_private_key_file {
+            if !std::path::Path::new(key_file).is_file() {
+                err!(format!("`DKIM_PRIVATE_KEY_FILE` doesn't exist: {}", key_file))
+            }
+        }
+
+        if cfg.mail_queue_workers < 1 {
+            err!("`MAIL_QUEUE_WORKERS` needs to be at least 1")
+        }
+
+        if cfg.mail_max_attempts < 1 {
+            err!("`MAIL_MAX_ATTEMPTS` needs to be at least 1")
+        }
+
+        if cfg._enable_email_2fa && !is_mail_configured(cfg) {
             err!("To enable email 2FA, SMTP must be configured")
         }
 
@@ -578,6 +718,84 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
         }
     }
 
+    match cfg.attachments_storage.as_str() {
+        "filesystem" => (),
+        "s3" => {
+            if cfg.s3_endpoint.is_none()
+                || cfg.s3_bucket.is_none()
+                || cfg.s3_access_key.is_none()
+                || cfg.s3_secret_key.is_none()
+            {
+                err!("`S3_ENDPOINT`, `S3_BUCKET`, `S3_ACCESS_KEY` and `S3_SECRET_KEY` need to be set to use S3 storage")
+            }
+
+            if let Some(ref endpoint) = cfg.s3_endpoint {
+                match Url::parse(endpoint) {
+                    Ok(u) if u.scheme() == "http" || u.scheme() == "https" => (),
+                    _ => err!("`S3_ENDPOINT` needs to be a valid URL, including the protocol (http, https)"),
+                }
+            }
+        }
+        _ => err!("`ATTACHMENTS_STORAGE` needs to be one of: filesystem, s3"),
+    }
+
+    if let Some(ref key) = cfg.storage_encryption_key {
+        if key.len() < 32 {
+            err!("`STORAGE_ENCRYPTION_KEY` needs to be at least 32 characters long, generate one with `openssl rand -base64 48`")
+        }
+    }
+
+    match cfg.password_hash_algorithm.as_str() {
+        "argon2id" => {
+            if cfg.password_argon2_iterations < 1 {
+                err!("`PASSWORD_ARGON2_ITERATIONS` must be at least 1")
+            }
+            if !(1..=16).contains(&cfg.password_argon2_parallelism) {
+                err!("`PASSWORD_ARGON2_PARALLELISM` must be between 1 and 16")
+            }
+            // Argon2 also needs 8 KiB per lane, which is always the case with at most 16 lanes
+            // Every login hashes the password, so a huge value lets a few concurrent logins exhaust the server memory
+            if !(8 * 1024..=1024 * 1024).contains(&cfg.password_argon2_memory) {
+                err!("`PASSWORD_ARGON2_MEMORY` must be between 8192 KiB and 1048576 KiB (1 GiB)")
+            }
+        }
+        "pbkdf2" => (),
+        _ => err!("`PASSWORD_HASH_ALGORITHM` must be one of: argon2id, pbkdf2"),
+    }
+
+    if cfg.password_iterations < 1 {
+        err!("`PASSWORD_ITERATIONS` must be at least 1")
+    }
+
+    if cfg.access_token_lifetime < 60 {
+        err!("`ACCESS_TOKEN_LIFETIME` must be at least 60 seconds")
+    }
+
+    // Otherwise the sessions would expire before the clients have a chance to refresh their access token
+    if cfg.session_idle_lifetime > 0 && cfg.session_idle_lifetime < cfg.access_token_lifetime {
+        err!("`SESSION_IDLE_LIFETIME` can't be lower than `ACCESS_TOKEN_LIFETIME`")
+    }
+    if cfg.session_absolute_lifetime > 0 && cfg.session_absolute_lifetime < cfg.access_token_lifetime {
+        err!("`SESSION_ABSOLUTE_LIFETIME` can't be lower than `ACCESS_TOKEN_LIFETIME`")
+    }
+
+    match cfg.icon_service.as_str() {
+        "internal" | "bitwarden" | "duckduckgo" | "google" => (),
+        service => {
+            if !service.starts_with("http") || service.matches("{}").count() != 1 {
+                err!("`ICON_SERVICE` must be a predefined service, or an URL with exactly one `{}`")
+            }
+        }
+    }
+
+    if ![301, 302, 307, 308].contains(&cfg.icon_redirect_code) {
+        err!("`ICON_REDIRECT_CODE` must be one of 301, 302, 307 or 308")
+    }
+
+    if !["none", "png", "svg"].contains(&cfg.icon_fallback.as_str()) {
+        err!("`ICON_FALLBACK` must be one of: none, png, svg")
+    }
+
     // Check if the icon blacklist regex is valid
     if let Some(ref r) = cfg.icon_blacklist_regex {
         let validate_regex = Regex::new(&r);
@@ -590,6 +808,27 @@ fn validate_config(cfg: &ConfigItems) -> Result<(), Error> {
     Ok(())
 }
 
+/// Returns the URL template of the external icon service, or an empty string when using the internal one
+fn generate_icon_service_url(icon_service: &str) -> String {
+    match icon_service {
+        "internal" => "",
+        "bitwarden" => "https://icons.bitwarden.net/{}/icon.png",
+        "duckduckgo" => "https://icons.duckduckgo.com/ip3/{}.ico",
+        "google" => "https://www.google.com/s2/favicons?domain={}&sz=32",
+        custom => custom,
+    }
+    .to_string()
+}
+
+/// Checks if the settings needed by the selected mail transport are set
+fn is_mail_configured(cfg: &ConfigItems) -> bool {
+    cfg._enable_smtp
+        && match cfg.mail_transport.as_str() {
+            "smtp" => cfg.smtp_host.is_some(),
+            _ => !cfg.smtp_from.is_empty(),
+        }
+}
+
 /// Extracts an RFC 6454 web origin from a URL.
 fn extract_url_origin(url: &str) -> String {
     match Url::parse(url) {
@@ -746,9 +985,12 @@ impl Config {
     pub fn public_rsa_key(&self) -> String {
         format!("{}.pub.der", CONFIG.rsa_key_filename())
     }
+    pub fn retired_rsa_keys_folder(&self) -> String {
+        format!("{}.retired", CONFIG.rsa_key_filename())
+    }
     pub fn mail_enabled(&self) -> bool {
         let inner = &self.inner.read().unwrap().config;
-        inner._enable_smtp && inner.smtp_host.is_some()
+        is_mail_configured(inner)
     }
 
     pub fn get_duo_akey(&self) -> String {
@@ -790,6 +1032,14 @@ impl Config {
             hb.render(name, data).map_err(Into::into)
         }
     }
+
+    pub fn has_template(&self, name: &str) -> bool {
+        if CONFIG.reload_templates() {
+            load_templates(CONFIG.templates_folder()).get_template(name).is_some()
+        } else {
+            self.inner.read().unwrap().templates.get_template(name).is_some()
+        }
+    }
 }
 
 use handlebars::{Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderError, Renderable};
@@ -817,6 +1067,7 @@ where
     }
 
     // First register default templates here
+    reg!("email/account_exists", ".html");
     reg!("email/change_email", ".html");
     reg!("email/delete_account", ".html");
     reg!("email/invite_accepted", ".html");
@@ -824,6 +1075,14 @@ where
     reg!("email/new_device_logged_in", ".html");
     reg!("email/pw_hint_none", ".html");
     reg!("email/pw_hint_some", ".html");
+    reg!("email/security_admin_action", ".html");
+    reg!("email/security_email_changed", ".html");
+    reg!("email/security_failed_twofactor", ".html");
+    reg!("email/security_kdf_changed", ".html");
+    reg!("email/security_key_rotated", ".html");
+    reg!("email/security_password_changed", ".html");
+    reg!("email/security_twofactor_added", ".html");
+    reg!("email/security_twofactor_removed", ".html");
     reg!("email/send_org_invite", ".html");
     reg!("email/twofactor_email", ".html");
     reg!("email/verify_email", ".html");
@@ -831,6 +1090,10 @@ where
     reg!("email/welcome_must_verify", ".html");
     reg!("email/smtp_test", ".html");
 
+    // Translations, only used when the user has selected that language
+    reg!("email/send_org_invite.de", ".html");
+    reg!("email/twofactor_email.de", ".html");
+
     reg!("admin/base");
     reg!("admin/login");
     reg!("admin/settings");
@@ -838,6 +1101,8 @@ where
     reg!("admin/organizations");
     reg!("admin/diagnostics");
 
+    reg!("security_notifications");
+
     // And then load user templates to overwrite the defaults
     // Use .hbs extension for the files
     // Templates get registered with their relative name
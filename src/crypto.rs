@@ -24,6 +24,48 @@ pub fn verify_password_hash(secret: &[u8], salt: &[u8], previous: &[u8], iterati
     pbkdf2::verify(DIGEST_ALG, iterations, salt, secret, previous).is_ok()
 }
 
+//
+// Argon2 derivation
+//
+// The hashes are stored in their encoded form, which also contains the parameters used to generate them.
+pub fn hash_password_argon2(
+    secret: &[u8],
+    salt: &[u8],
+    memory: u32,
+    iterations: u32,
+    parallelism: u32,
+) -> Result<Vec<u8>, Error> {
+    let config = argon2_config(memory, iterations, parallelism);
+    Ok(argon2::hash_encoded(secret, salt, &config)?.into_bytes())
+}
+
+pub fn verify_password_argon2(secret: &[u8], encoded: &[u8]) -> bool {
+    match std::str::from_utf8(encoded) {
+        Ok(encoded) => argon2::verify_encoded(encoded, secret).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// Checks if the encoded hash was generated with the given parameters
+pub fn argon2_params_match(encoded: &[u8], memory: u32, iterations: u32, parallelism: u32) -> bool {
+    let prefix = format!("$argon2id$v=19$m={},t={},p={}$", memory, iterations, parallelism);
+    encoded.starts_with(prefix.as_bytes())
+}
+
+fn argon2_config<'a>(memory: u32, iterations: u32, parallelism: u32) -> argon2::Config<'a> {
+    argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        version: argon2::Version::Version13,
+        mem_cost: memory,
+        time_cost: iterations,
+        lanes: parallelism,
+        thread_mode: argon2::ThreadMode::Sequential,
+        secret: &[],
+        ad: &[],
+        hash_length: OUTPUT_LEN as u32,
+    }
+}
+
 //
 // HMAC
 //
@@ -37,6 +37,7 @@ macro_rules! generate_connections {
         pub enum DbConn { $( #[cfg($name)] $name(PooledConnection<ConnectionManager< $ty >>), )+ }
 
         #[allow(non_camel_case_types)]
+        #[derive(Clone)]
         pub enum DbPool { $( #[cfg($name)] $name(Pool<ConnectionManager< $ty >>), )+ }
 
         impl DbPool {
@@ -80,6 +81,25 @@ generate_connections! {
     postgresql: diesel::pg::PgConnection
 }
 
+impl DbConn {
+    /// Runs the function inside a database transaction, which is committed if it returns `Ok` and rolled back otherwise.
+    /// The same connection is used as usual by the models inside the function.
+    pub fn transaction<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> Result<T, Error>,
+    {
+        use diesel::Connection;
+        match self {
+            #[cfg(sqlite)]
+            DbConn::sqlite(conn) => conn.transaction(f),
+            #[cfg(mysql)]
+            DbConn::mysql(conn) => conn.transaction(f),
+            #[cfg(postgresql)]
+            DbConn::postgresql(conn) => conn.transaction(f),
+        }
+    }
+}
+
 impl DbConnType {
     pub fn from_url(url: &str) -> Result<DbConnType, Error> {
         // Mysql
@@ -118,7 +138,7 @@ macro_rules! db_run {
     };
 
     // Different code for each db
-    ( $conn:ident: $( $($db:ident),+ $body:block )+ ) => {
+    ( $conn:ident: $( $($db:ident),+ $body:block )+ ) => {{
         #[allow(unused)] use diesel::prelude::*;
         match $conn {
             $($(
@@ -133,7 +153,7 @@ macro_rules! db_run {
                 },
             )+)+
         }
-    };
+    }};
 }
 
 
@@ -1,7 +1,7 @@
 use serde_json::Value;
 
 use super::Cipher;
-use crate::CONFIG;
+use crate::auth::{encode_jwt, generate_file_download_claims};
 
 db_object! {
     #[derive(Debug, Identifiable, Queryable, Insertable, Associations, AsChangeset)]
@@ -13,14 +13,14 @@ db_object! {
         pub id: String,
         pub cipher_uuid: String,
         pub file_name: String,
-        pub file_size: i32,
+        pub file_size: i64,
         pub akey: Option<String>,
     }
 }
 
 /// Local methods
 impl Attachment {
-    pub const fn new(id: String, cipher_uuid: String, file_name: String, file_size: i32) -> Self {
+    pub const fn new(id: String, cipher_uuid: String, file_name: String, file_size: i64) -> Self {
         Self {
             id,
             cipher_uuid,
@@ -30,19 +30,31 @@ impl Attachment {
         }
     }
 
-    pub fn get_file_path(&self) -> String {
-        format!("{}/{}/{}", CONFIG.attachments_folder(), self.cipher_uuid, self.id)
+    /// Key of the attachment data in the attachments storage
+    pub fn get_storage_key(&self) -> String {
+        format!("{}/{}", self.cipher_uuid, self.id)
     }
 
+    /// URL of the attachment file, signed with a short-lived token that authorizes the download
+    pub fn get_download_url(&self, host: &str) -> String {
+        let claims = generate_file_download_claims(self.cipher_uuid.clone(), self.id.clone());
+        format!("{}?token={}", self.get_url(host), encode_jwt(&claims))
+    }
+
+    fn get_url(&self, host: &str) -> String {
+        format!("{}/attachments/{}/{}", host, self.cipher_uuid, self.id)
+    }
+
+    /// The URL isn't signed here, so the sync responses stay the same. The clients get the signed URL
+    /// from `/ciphers/<id>/attachment/<aid>` right before downloading the file
     pub fn to_json(&self, host: &str) -> Value {
         use crate::util::get_display_size;
 
-        let web_path = format!("{}/attachments/{}/{}", host, self.cipher_uuid, self.id);
         let display_size = get_display_size(self.file_size);
 
         json!({
             "Id": self.id,
-            "Url": web_path,
+            "Url": self.get_url(host),
             "FileName": self.file_name,
             "Size": self.file_size.to_string(),
             "SizeName": display_size,
@@ -52,11 +64,22 @@ impl Attachment {
     }
 }
 
+use bigdecimal::BigDecimal;
+use num_traits::ToPrimitive;
+
 use crate::db::DbConn;
 
 use crate::api::EmptyResult;
 use crate::error::MapResult;
 
+/// The sums of BigInt columns are returned as Numeric, saturate in the (unlikely) case it doesn't fit
+fn sum_to_i64(sum: Option<BigDecimal>) -> i64 {
+    match sum {
+        Some(sum) => sum.to_i64().unwrap_or(i64::MAX),
+        None => 0,
+    }
+}
+
 /// Database methods
 impl Attachment {
 
@@ -93,18 +116,31 @@ impl Attachment {
     }
 
     pub fn delete(self, conn: &DbConn) -> EmptyResult {
+        self.delete_row(conn)?;
+        crate::storage::attachments().delete(&self.get_storage_key())?;
+        Ok(())
+    }
+
+    /// Deletes only the database row. Inside a transaction, the file is deleted with `delete_files` after the commit,
+    /// as it couldn't be restored if the transaction was rolled back
+    pub fn delete_row(&self, conn: &DbConn) -> EmptyResult {
         db_run! { conn: {
             crate::util::retry(
                 || diesel::delete(attachments::table.filter(attachments::id.eq(&self.id))).execute(conn),
                 10,
             )
-            .map_res("Error deleting attachment")?;
-
-            crate::util::delete_file(&self.get_file_path())?;
-            Ok(())
+            .map_res("Error deleting attachment")
         }}
     }
 
+    pub fn delete_files(attachments: &[Self]) {
+        for attachment in attachments {
+            if let Err(e) = crate::storage::attachments().delete(&attachment.get_storage_key()) {
+                error!("Error deleting attachment file {}: {:?}", attachment.get_storage_key(), e);
+            }
+        }
+    }
+
     pub fn delete_all_by_cipher(cipher_uuid: &str, conn: &DbConn) -> EmptyResult {
         for attachment in Attachment::find_by_cipher(&cipher_uuid, &conn) {
             attachment.delete(&conn)?;
@@ -142,15 +178,27 @@ impl Attachment {
         }}
     }
 
+    pub fn find_by_user(user_uuid: &str, conn: &DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            attachments::table
+                .inner_join(ciphers::table.on(ciphers::uuid.eq(attachments::cipher_uuid)))
+                .filter(ciphers::user_uuid.eq(user_uuid))
+                .select(attachments::all_columns)
+                .load::<AttachmentDb>(conn)
+                .expect("Error loading attachments")
+                .from_db()
+        }}
+    }
+
     pub fn size_by_user(user_uuid: &str, conn: &DbConn) -> i64 {
         db_run! { conn: {
-            let result: Option<i64> = attachments::table
+            let result: Option<BigDecimal> = attachments::table
                 .left_join(ciphers::table.on(ciphers::uuid.eq(attachments::cipher_uuid)))
                 .filter(ciphers::user_uuid.eq(user_uuid))
                 .select(diesel::dsl::sum(attachments::file_size))
                 .first(conn)
                 .expect("Error loading user attachment total size");
-            result.unwrap_or(0)
+            sum_to_i64(result)
         }}
     }
 
@@ -167,13 +215,13 @@ impl Attachment {
 
     pub fn size_by_org(org_uuid: &str, conn: &DbConn) -> i64 {
         db_run! { conn: {
-            let result: Option<i64> = attachments::table
+            let result: Option<BigDecimal> = attachments::table
                 .left_join(ciphers::table.on(ciphers::uuid.eq(attachments::cipher_uuid)))
                 .filter(ciphers::organization_uuid.eq(org_uuid))
                 .select(diesel::dsl::sum(attachments::file_size))
                 .first(conn)
                 .expect("Error loading user attachment total size");
-            result.unwrap_or(0)
+            sum_to_i64(result)
         }}
     }
 
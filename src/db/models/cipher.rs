@@ -1,3 +1,5 @@
+use std::collections::{HashMap, HashSet};
+
 use chrono::{NaiveDateTime, Utc};
 use serde_json::Value;
 
@@ -77,24 +79,88 @@ use crate::db::DbConn;
 use crate::api::EmptyResult;
 use crate::error::MapResult;
 
+/// Data needed to generate the JSON of many ciphers for the same user, loaded with a few queries
+/// for all of them at once instead of several queries per cipher. Used by sync and the cipher lists.
+pub struct CipherSyncData {
+    pub cipher_attachments: HashMap<String, Vec<Attachment>>,
+    pub cipher_folders: HashMap<String, String>,
+    pub cipher_favorites: HashSet<String>,
+    pub cipher_collections: HashMap<String, Vec<String>>,
+    pub cipher_access_restrictions: HashMap<String, (bool, bool)>,
+    pub user_organizations: HashMap<String, UserOrganization>,
+}
+
+impl CipherSyncData {
+    pub fn new(user_uuid: &str, ciphers: &[Cipher], conn: &DbConn) -> Self {
+        let cipher_uuids = ciphers.iter().map(|c| c.uuid.clone()).collect();
+
+        let mut cipher_attachments: HashMap<String, Vec<Attachment>> = HashMap::new();
+        for attachment in Attachment::find_by_ciphers(cipher_uuids, conn) {
+            cipher_attachments.entry(attachment.cipher_uuid.clone()).or_default().push(attachment);
+        }
+
+        let cipher_folders = FolderCipher::find_by_user(user_uuid, conn)
+            .into_iter()
+            .map(|fc| (fc.cipher_uuid, fc.folder_uuid))
+            .collect();
+
+        let cipher_favorites = Favorite::get_all_cipher_uuid_by_user(user_uuid, conn).into_iter().collect();
+
+        let mut cipher_collections: HashMap<String, Vec<String>> = HashMap::new();
+        for (cipher_uuid, collection_uuid) in Cipher::get_collections_with_cipher_by_user(user_uuid, conn) {
+            cipher_collections.entry(cipher_uuid).or_default().push(collection_uuid);
+        }
+
+        // Same as in get_access_restrictions, do a boolean OR of the flags of every collection
+        let mut cipher_access_restrictions: HashMap<String, (bool, bool)> = HashMap::new();
+        for (cipher_uuid, ro, hp) in Cipher::get_access_restrictions_by_user(user_uuid, conn) {
+            let (read_only, hide_passwords) = cipher_access_restrictions.entry(cipher_uuid).or_default();
+            *read_only |= ro;
+            *hide_passwords |= hp;
+        }
+
+        let user_organizations = UserOrganization::find_any_state_by_user(user_uuid, conn)
+            .into_iter()
+            .map(|uo| (uo.org_uuid.clone(), uo))
+            .collect();
+
+        Self {
+            cipher_attachments,
+            cipher_folders,
+            cipher_favorites,
+            cipher_collections,
+            cipher_access_restrictions,
+            user_organizations,
+        }
+    }
+}
+
 /// Database methods
 impl Cipher {
-    pub fn to_json(&self, host: &str, user_uuid: &str, conn: &DbConn) -> Value {
+    pub fn to_json(&self, host: &str, user_uuid: &str, cipher_sync_data: Option<&CipherSyncData>, conn: &DbConn) -> Value {
         use crate::util::format_date;
 
-        let attachments = Attachment::find_by_cipher(&self.uuid, conn);
         // When there are no attachments use null instead of an empty array
-        let attachments_json = if attachments.is_empty() {
-            Value::Null
-        } else {
-            attachments.iter().map(|c| c.to_json(host)).collect()
+        let attachments_json = match cipher_sync_data {
+            Some(cipher_sync_data) => match cipher_sync_data.cipher_attachments.get(&self.uuid) {
+                Some(attachments) => attachments.iter().map(|c| c.to_json(host)).collect(),
+                None => Value::Null,
+            },
+            None => {
+                let attachments = Attachment::find_by_cipher(&self.uuid, conn);
+                if attachments.is_empty() {
+                    Value::Null
+                } else {
+                    attachments.iter().map(|c| c.to_json(host)).collect()
+                }
+            }
         };
 
         let fields_json = self.fields.as_ref().and_then(|s| serde_json::from_str(s).ok()).unwrap_or(Value::Null);
         let password_history_json = self.password_history.as_ref().and_then(|s| serde_json::from_str(s).ok()).unwrap_or(Value::Null);
 
         let (read_only, hide_passwords) =
-            match self.get_access_restrictions(&user_uuid, conn) {
+            match self.get_access_restrictions(&user_uuid, cipher_sync_data, conn) {
                 Some((ro, hp)) => (ro, hp),
                 None => {
                     error!("Cipher ownership assertion failure");
@@ -141,8 +207,8 @@ impl Cipher {
             "Type": self.atype,
             "RevisionDate": format_date(&self.updated_at),
             "DeletedDate": self.deleted_at.map_or(Value::Null, |d| Value::String(format_date(&d))),
-            "FolderId": self.get_folder_uuid(&user_uuid, conn),
-            "Favorite": self.is_favorite(&user_uuid, conn),
+            "FolderId": self.get_folder_uuid(&user_uuid, cipher_sync_data, conn),
+            "Favorite": self.is_favorite(&user_uuid, cipher_sync_data, conn),
             "OrganizationId": self.organization_uuid,
             "Attachments": attachments_json,
             // We have UseTotp set to true by default within the Organization model.
@@ -150,7 +216,7 @@ impl Cipher {
             "OrganizationUseTotp": true,
 
             // This field is specific to the cipherDetails type.
-            "CollectionIds": self.get_collections(user_uuid, conn),
+            "CollectionIds": self.get_collections(user_uuid, cipher_sync_data, conn),
 
             "Name": self.name,
             "Notes": self.notes,
@@ -274,7 +340,7 @@ impl Cipher {
     pub fn move_to_folder(&self, folder_uuid: Option<String>, user_uuid: &str, conn: &DbConn) -> EmptyResult {
         User::update_uuid_revision(user_uuid, conn);
 
-        match (self.get_folder_uuid(&user_uuid, conn), folder_uuid) {
+        match (self.get_folder_uuid(&user_uuid, None, conn), folder_uuid) {
             // No changes
             (None, None) => Ok(()),
             (Some(ref old), Some(ref new)) if old == new => Ok(()),
@@ -304,9 +370,13 @@ impl Cipher {
     }
 
     /// Returns whether this cipher is owned by an org in which the user has full access.
-    pub fn is_in_full_access_org(&self, user_uuid: &str, conn: &DbConn) -> bool {
+    pub fn is_in_full_access_org(&self, user_uuid: &str, cipher_sync_data: Option<&CipherSyncData>, conn: &DbConn) -> bool {
         if let Some(ref org_uuid) = self.organization_uuid {
-            if let Some(user_org) = UserOrganization::find_by_user_and_org(&user_uuid, &org_uuid, conn) {
+            if let Some(cipher_sync_data) = cipher_sync_data {
+                if let Some(user_org) = cipher_sync_data.user_organizations.get(org_uuid) {
+                    return user_org.has_full_access();
+                }
+            } else if let Some(user_org) = UserOrganization::find_by_user_and_org(&user_uuid, &org_uuid, conn) {
                 return user_org.has_full_access();
             }
         }
@@ -319,14 +389,20 @@ impl Cipher {
     /// not in any collection the user has access to. Otherwise, the user has
     /// access to this cipher, and Some(read_only, hide_passwords) represents
     /// the access restrictions.
-    pub fn get_access_restrictions(&self, user_uuid: &str, conn: &DbConn) -> Option<(bool, bool)> {
+    pub fn get_access_restrictions(&self, user_uuid: &str, cipher_sync_data: Option<&CipherSyncData>, conn: &DbConn) -> Option<(bool, bool)> {
         // Check whether this cipher is directly owned by the user, or is in
         // a collection that the user has full access to. If so, there are no
         // access restrictions.
-        if self.is_owned_by_user(&user_uuid) || self.is_in_full_access_org(&user_uuid, &conn) {
+        if self.is_owned_by_user(&user_uuid) || self.is_in_full_access_org(&user_uuid, cipher_sync_data, &conn) {
             return Some((false, false));
         }
 
+        // The preloaded flags are already combined for every collection, like below.
+        // No rows for this cipher also means no restrictions, the same as loading an empty list.
+        if let Some(cipher_sync_data) = cipher_sync_data {
+            return Some(cipher_sync_data.cipher_access_restrictions.get(&self.uuid).copied().unwrap_or_default());
+        }
+
         db_run! {conn: {
             // Check whether this cipher is in any collections accessible to the
             // user. If so, retrieve the access flags for each collection.
@@ -364,19 +440,22 @@ impl Cipher {
     }
 
     pub fn is_write_accessible_to_user(&self, user_uuid: &str, conn: &DbConn) -> bool {
-        match self.get_access_restrictions(&user_uuid, &conn) {
+        match self.get_access_restrictions(&user_uuid, None, &conn) {
             Some((read_only, _hide_passwords)) => !read_only,
             None => false,
         }
     }
 
     pub fn is_accessible_to_user(&self, user_uuid: &str, conn: &DbConn) -> bool {
-        self.get_access_restrictions(&user_uuid, &conn).is_some()
+        self.get_access_restrictions(&user_uuid, None, &conn).is_some()
     }
 
     // Returns whether this cipher is a favorite of the specified user.
-    pub fn is_favorite(&self, user_uuid: &str, conn: &DbConn) -> bool {
-        Favorite::is_favorite(&self.uuid, user_uuid, conn)
+    pub fn is_favorite(&self, user_uuid: &str, cipher_sync_data: Option<&CipherSyncData>, conn: &DbConn) -> bool {
+        match cipher_sync_data {
+            Some(cipher_sync_data) => cipher_sync_data.cipher_favorites.contains(&self.uuid),
+            None => Favorite::is_favorite(&self.uuid, user_uuid, conn),
+        }
     }
 
     // Sets whether this cipher is a favorite of the specified user.
@@ -387,7 +466,11 @@ impl Cipher {
         }
     }
 
-    pub fn get_folder_uuid(&self, user_uuid: &str, conn: &DbConn) -> Option<String> {
+    pub fn get_folder_uuid(&self, user_uuid: &str, cipher_sync_data: Option<&CipherSyncData>, conn: &DbConn) -> Option<String> {
+        if let Some(cipher_sync_data) = cipher_sync_data {
+            return cipher_sync_data.cipher_folders.get(&self.uuid).cloned();
+        }
+
         db_run! {conn: {
             folders_ciphers::table
                 .inner_join(folders::table)
@@ -511,7 +594,11 @@ impl Cipher {
         }}
     }
 
-    pub fn get_collections(&self, user_id: &str, conn: &DbConn) -> Vec<String> {
+    pub fn get_collections(&self, user_id: &str, cipher_sync_data: Option<&CipherSyncData>, conn: &DbConn) -> Vec<String> {
+        if let Some(cipher_sync_data) = cipher_sync_data {
+            return cipher_sync_data.cipher_collections.get(&self.uuid).cloned().unwrap_or_default();
+        }
+
         db_run! {conn: {
             ciphers_collections::table
             .inner_join(collections::table.on(
@@ -537,4 +624,45 @@ impl Cipher {
             .load::<String>(conn).unwrap_or_default()
         }}
     }
+
+    /// Same as get_collections, but for all the ciphers of the user at once.
+    /// Returns (cipher_uuid, collection_uuid) pairs.
+    pub fn get_collections_with_cipher_by_user(user_id: &str, conn: &DbConn) -> Vec<(String, String)> {
+        db_run! {conn: {
+            ciphers_collections::table
+            .inner_join(collections::table.on(
+                collections::uuid.eq(ciphers_collections::collection_uuid)
+            ))
+            .inner_join(users_organizations::table.on(
+                users_organizations::org_uuid.eq(collections::org_uuid).and(
+                    users_organizations::user_uuid.eq(user_id)
+                )
+            ))
+            .left_join(users_collections::table.on(
+                users_collections::collection_uuid.eq(ciphers_collections::collection_uuid).and(
+                    users_collections::user_uuid.eq(user_id)
+                )
+            ))
+            .filter(users_collections::user_uuid.eq(user_id).or( // User has access to collection
+                users_organizations::access_all.eq(true).or( // User has access all
+                    users_organizations::atype.le(UserOrgType::Admin as i32) // User is admin or owner
+                )
+            ))
+            .select((ciphers_collections::cipher_uuid, ciphers_collections::collection_uuid))
+            .load::<(String, String)>(conn).unwrap_or_default()
+        }}
+    }
+
+    /// Same as the query in get_access_restrictions, but for all the ciphers of the user at once.
+    /// Returns (cipher_uuid, read_only, hide_passwords) for every collection the user has access to.
+    pub fn get_access_restrictions_by_user(user_uuid: &str, conn: &DbConn) -> Vec<(String, bool, bool)> {
+        db_run! {conn: {
+            ciphers_collections::table
+                .inner_join(users_collections::table.on(
+                    ciphers_collections::collection_uuid.eq(users_collections::collection_uuid)
+                        .and(users_collections::user_uuid.eq(user_uuid))))
+                .select((ciphers_collections::cipher_uuid, users_collections::read_only, users_collections::hide_passwords))
+                .load::<(String, bool, bool)>(conn).unwrap_or_default()
+        }}
+    }
 }
@@ -1,7 +1,8 @@
 use chrono::{NaiveDateTime, Utc};
+use serde_json::Value;
 
 use super::User;
-use crate::CONFIG;
+use crate::{util::format_date, CONFIG};
 
 db_object! {
     #[derive(Debug, Identifiable, Queryable, Insertable, Associations, AsChangeset)]
@@ -24,9 +25,18 @@ db_object! {
         pub refresh_token: String,
 
         pub twofactor_remember: Option<String>,
+
+        // Kept after rotating the refresh token, to detect when a stolen token is used
+        pub previous_refresh_token: Option<String>,
+        pub session_created_at: Option<NaiveDateTime>, // None for the sessions started before it was stored
+        pub last_refresh_at: Option<NaiveDateTime>,    // Unlike updated_at, only changed when the tokens are refreshed
     }
 }
 
+// Clients can refresh their tokens concurrently, like the popup and the background page of the browser extensions,
+// so the previous refresh token is still accepted for a few seconds after it was rotated
+const REFRESH_TOKEN_REUSE_WINDOW: i64 = 10;
+
 /// Local methods
 impl Device {
     pub fn new(uuid: String, user_uuid: String, name: String, atype: i32) -> Self {
@@ -44,6 +54,10 @@ impl Device {
             push_token: None,
             refresh_token: String::new(),
             twofactor_remember: None,
+
+            previous_refresh_token: None,
+            session_created_at: Some(now),
+            last_refresh_at: None,
         }
     }
 
@@ -61,18 +75,112 @@ impl Device {
         self.twofactor_remember = None;
     }
 
-    pub fn refresh_tokens(&mut self, user: &super::User, orgs: Vec<super::UserOrganization>) -> (String, i64) {
-        // If there is no refresh token, we create one
-        if self.refresh_token.is_empty() {
-            use crate::crypto;
-            use data_encoding::BASE64URL;
+    /// Logs out this device: the refresh token can't be used anymore and the remembered
+    /// two-step login is dropped, but the row is kept so it still shows up in the device list.
+    pub fn revoke(&mut self) {
+        self.refresh_token = String::new();
+        self.previous_refresh_token = None;
+        self.twofactor_remember = None;
+    }
 
-            self.refresh_token = BASE64URL.encode(&crypto::get_random_64());
+    /// Starts a new session after the user logged in with their password, discarding the current refresh token
+    pub fn start_session(&mut self) {
+        self.refresh_token = String::new();
+        self.previous_refresh_token = None;
+        self.session_created_at = Some(Utc::now().naive_utc());
+    }
+
+    /// Checks the idle and absolute session lifetimes, before using the refresh token
+    pub fn is_session_expired(&self) -> bool {
+        let now = Utc::now().naive_utc();
+
+        let idle_lifetime = CONFIG.session_idle_lifetime() as i64;
+        // The sessions last refreshed before it was stored don't have last_refresh_at
+        let last_refresh_at = self.last_refresh_at.unwrap_or(self.updated_at);
+        if idle_lifetime > 0 && now.signed_duration_since(last_refresh_at).num_seconds() > idle_lifetime {
+            return true;
         }
 
+        let absolute_lifetime = CONFIG.session_absolute_lifetime() as i64;
+        let session_created_at = self.session_created_at.unwrap_or(self.created_at);
+        absolute_lifetime > 0 && now.signed_duration_since(session_created_at).num_seconds() > absolute_lifetime
+    }
+
+    /// Checks if the previous refresh token was rotated recently enough to still be accepted
+    pub fn is_previous_token_reusable(&self) -> bool {
+        match self.last_refresh_at {
+            Some(last_refresh_at) => {
+                Utc::now().naive_utc().signed_duration_since(last_refresh_at).num_seconds() <= REFRESH_TOKEN_REUSE_WINDOW
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.refresh_token.is_empty()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "Id": self.uuid,
+            "Name": self.name,
+            "Type": self.atype,
+            "TypeName": Self::type_name(self.atype),
+            "Identifier": self.uuid,
+            "CreationDate": format_date(&self.created_at),
+            "RevisionDate": format_date(&self.updated_at),
+            "Active": !self.is_revoked(),
+            "Object": "device",
+        })
+    }
+
+    // https://github.com/bitwarden/server/blob/master/src/Core/Enums/DeviceType.cs
+    pub fn type_name(atype: i32) -> &'static str {
+        match atype {
+            0 => "Android",
+            1 => "iOS",
+            2 => "Chrome Extension",
+            3 => "Firefox Extension",
+            4 => "Opera Extension",
+            5 => "Edge Extension",
+            6 => "Windows",
+            7 => "macOS",
+            8 => "Linux",
+            9 => "Chrome",
+            10 => "Firefox",
+            11 => "Opera",
+            12 => "Edge",
+            13 => "Internet Explorer",
+            15 => "Android",
+            16 => "Windows UWP",
+            17 => "Safari",
+            18 => "Vivaldi",
+            19 => "Vivaldi Extension",
+            20 => "Safari Extension",
+            _ => "Unknown Browser",
+        }
+    }
+
+    pub fn refresh_tokens(&mut self, user: &super::User, orgs: Vec<super::UserOrganization>) -> (String, i64) {
+        // Rotate the refresh token every time it's used, so a leaked token stops working after the next refresh
+        use crate::crypto;
+        use data_encoding::BASE64URL;
+
+        let new_token = BASE64URL.encode(&crypto::get_random_64());
+        let previous_token = std::mem::replace(&mut self.refresh_token, new_token);
+        self.previous_refresh_token = Some(previous_token).filter(|t| !t.is_empty());
+
         // Update the expiration of the device and the last update date
         let time_now = Utc::now().naive_utc();
         self.updated_at = time_now;
+        self.last_refresh_at = Some(time_now);
+
+        self.access_token(user, orgs)
+    }
+
+    /// Creates a new access token without rotating the refresh token
+    pub fn access_token(&self, user: &super::User, orgs: Vec<super::UserOrganization>) -> (String, i64) {
+        let time_now = Utc::now().naive_utc();
 
         let orgowner: Vec<_> = orgs.iter().filter(|o| o.atype == 0).map(|o| o.org_uuid.clone()).collect();
         let orgadmin: Vec<_> = orgs.iter().filter(|o| o.atype == 1).map(|o| o.org_uuid.clone()).collect();
@@ -80,10 +188,11 @@ impl Device {
         let orgmanager: Vec<_> = orgs.iter().filter(|o| o.atype == 3).map(|o| o.org_uuid.clone()).collect();
 
         // Create the JWT claims struct, to send to the client
-        use crate::auth::{encode_jwt, LoginJWTClaims, DEFAULT_VALIDITY, JWT_LOGIN_ISSUER};
+        use crate::auth::{encode_jwt, login_validity, LoginJWTClaims, JWT_LOGIN_ISSUER};
+        let validity = login_validity();
         let claims = LoginJWTClaims {
             nbf: time_now.timestamp(),
-            exp: (time_now + *DEFAULT_VALIDITY).timestamp(),
+            exp: (time_now + validity).timestamp(),
             iss: JWT_LOGIN_ISSUER.to_string(),
             sub: user.uuid.to_string(),
 
@@ -103,7 +212,7 @@ impl Device {
             amr: vec!["Application".into()],
         };
 
-        (encode_jwt(&claims), DEFAULT_VALIDITY.num_seconds())
+        (encode_jwt(&claims), validity.num_seconds())
     }
 }
 
@@ -159,6 +268,17 @@ impl Device {
         }}
     }
 
+    pub fn find_by_uuid_and_user(uuid: &str, user_uuid: &str, conn: &DbConn) -> Option<Self> {
+        db_run! { conn: {
+            devices::table
+                .filter(devices::uuid.eq(uuid))
+                .filter(devices::user_uuid.eq(user_uuid))
+                .first::<DeviceDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
     pub fn find_by_refresh_token(refresh_token: &str, conn: &DbConn) -> Option<Self> {
         db_run! { conn: {
             devices::table
@@ -169,10 +289,21 @@ impl Device {
         }}
     }
 
+    pub fn find_by_previous_refresh_token(refresh_token: &str, conn: &DbConn) -> Option<Self> {
+        db_run! { conn: {
+            devices::table
+                .filter(devices::previous_refresh_token.eq(refresh_token))
+                .first::<DeviceDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
     pub fn find_by_user(user_uuid: &str, conn: &DbConn) -> Vec<Self> {
         db_run! { conn: {
             devices::table
                 .filter(devices::user_uuid.eq(user_uuid))
+                .order(devices::updated_at.desc())
                 .load::<DeviceDb>(conn)
                 .expect("Error loading devices")
                 .from_db()
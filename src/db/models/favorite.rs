@@ -80,4 +80,15 @@ impl Favorite {
                 .map_res("Error removing favorites by user")
         }}
     }
+
+    // Returns the uuids of all the ciphers the specified user has marked as favorite.
+    pub fn get_all_cipher_uuid_by_user(user_uuid: &str, conn: &DbConn) -> Vec<String> {
+        db_run! { conn: {
+            favorites::table
+                .filter(favorites::user_uuid.eq(user_uuid))
+                .select(favorites::cipher_uuid)
+                .load::<String>(conn)
+                .unwrap_or_default()
+        }}
+    }
 }
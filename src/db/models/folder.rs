@@ -216,4 +216,17 @@ impl FolderCipher {
                 .from_db()
         }}
     }
+
+    /// Return all the folder/cipher mappings of the folders owned by the user
+    pub fn find_by_user(user_uuid: &str, conn: &DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            folders_ciphers::table
+                .inner_join(folders::table)
+                .filter(folders::user_uuid.eq(user_uuid))
+                .select(folders_ciphers::all_columns)
+                .load::<FolderCipherDb>(conn)
+                .expect("Error loading folders")
+                .from_db()
+        }}
+    }
 }
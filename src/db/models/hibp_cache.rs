@@ -0,0 +1,105 @@
+use std::{fs::File, io::Write};
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use data_encoding::HEXLOWER;
+use once_cell::sync::Lazy;
+use ring::hmac;
+
+use crate::api::EmptyResult;
+use crate::crypto;
+use crate::db::DbConn;
+use crate::error::MapResult;
+use crate::util::read_file;
+use crate::CONFIG;
+
+// Secret used to key the cached accounts. It's stored so the cache entries are still valid when the server restarts.
+static HIBP_CACHE_KEY: Lazy<hmac::Key> = Lazy::new(|| {
+    let path = CONFIG.hibp_cache_key_filename();
+    let secret = match read_file(&path) {
+        Ok(secret) if secret.len() == 32 => secret,
+        _ => {
+            let secret = crypto::get_random(vec![0u8; 32]);
+            if let Err(e) = File::create(&path).and_then(|mut f| f.write_all(&secret)) {
+                error!("Error saving the HIBP cache key: {:?}", e);
+            }
+            secret
+        }
+    };
+    hmac::Key::new(hmac::HMAC_SHA256, &secret)
+});
+
+db_object! {
+    #[derive(Debug, Identifiable, Queryable, Insertable, AsChangeset)]
+    #[table_name = "hibp_cache"]
+    #[changeset_options(treat_none_as_null="true")]
+    #[primary_key(account_hash)]
+    pub struct HibpCache {
+        pub account_hash: String,
+        pub response: Option<String>, // None when the account has no breaches
+        pub created_at: NaiveDateTime,
+    }
+}
+
+/// Local methods
+impl HibpCache {
+    pub fn new(account: &str, response: Option<String>) -> Self {
+        Self {
+            account_hash: Self::hash_account(account),
+            response,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    // The accounts are not stored directly, they could be the email of someone without an account in the server.
+    // They are keyed with a server secret, so they can't be recovered by hashing a list of known emails
+    fn hash_account(account: &str) -> String {
+        HEXLOWER.encode(hmac::sign(&HIBP_CACHE_KEY, account.to_lowercase().as_bytes()).as_ref())
+    }
+}
+
+/// Database methods
+impl HibpCache {
+    pub fn save(&self, conn: &DbConn) -> EmptyResult {
+        db_run! { conn:
+            sqlite, mysql {
+                diesel::replace_into(hibp_cache::table)
+                    .values(HibpCacheDb::to_db(self))
+                    .execute(conn)
+                    .map_res("Error saving HIBP cache entry")
+            }
+            postgresql {
+                let value = HibpCacheDb::to_db(self);
+                diesel::insert_into(hibp_cache::table)
+                    .values(&value)
+                    .on_conflict(hibp_cache::account_hash)
+                    .do_update()
+                    .set(&value)
+                    .execute(conn)
+                    .map_res("Error saving HIBP cache entry")
+            }
+        }
+    }
+
+    /// Returns the cached response for the account, if it's not older than `ttl` seconds
+    pub fn find_by_account(account: &str, ttl: i64, conn: &DbConn) -> Option<Self> {
+        let account_hash = Self::hash_account(account);
+        let oldest = Utc::now().naive_utc() - Duration::seconds(ttl);
+        db_run! { conn: {
+            hibp_cache::table
+                .filter(hibp_cache::account_hash.eq(account_hash))
+                .filter(hibp_cache::created_at.ge(oldest))
+                .first::<HibpCacheDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub fn delete_expired(ttl: i64, conn: &DbConn) -> EmptyResult {
+        let oldest = Utc::now().naive_utc() - Duration::seconds(ttl);
+        db_run! { conn: {
+            diesel::delete(hibp_cache::table.filter(hibp_cache::created_at.lt(oldest)))
+                .execute(conn)
+                .map_res("Error deleting expired HIBP cache entries")
+        }}
+    }
+}
@@ -0,0 +1,161 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde_json::Value;
+
+use super::{Device, TwoFactorType, User};
+use crate::api::EmptyResult;
+use crate::db::DbConn;
+use crate::error::MapResult;
+use crate::CONFIG;
+
+db_object! {
+    #[derive(Debug, Identifiable, Queryable, Insertable, Associations)]
+    #[table_name = "login_history"]
+    #[belongs_to(User, foreign_key = "user_uuid")]
+    #[primary_key(uuid)]
+    pub struct LoginHistory {
+        pub uuid: String,
+        pub user_uuid: String,
+        pub created_at: NaiveDateTime,
+
+        pub ip_address: String,
+        pub device_type: Option<i32>,
+        pub device_name: Option<String>,
+        pub grant_type: String,
+        pub twofactor_type: Option<i32>,
+
+        pub success: bool,
+        pub failure_reason: Option<String>, // None when the login succeeded
+    }
+}
+
+/// Local methods
+impl LoginHistory {
+    pub fn new(
+        user_uuid: &str,
+        ip_address: String,
+        grant_type: &str,
+        device_type: Option<i32>,
+        device_name: Option<String>,
+    ) -> Self {
+        Self {
+            uuid: crate::util::get_uuid(),
+            user_uuid: user_uuid.to_string(),
+            created_at: Utc::now().naive_utc(),
+
+            ip_address,
+            device_type,
+            device_name,
+            grant_type: grant_type.to_string(),
+            twofactor_type: None,
+
+            success: true,
+            failure_reason: None,
+        }
+    }
+
+    pub fn failed(mut self, reason: &str) -> Self {
+        self.success = false;
+        self.failure_reason = Some(reason.to_string());
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        use crate::util::format_date;
+
+        json!({
+            "Id": self.uuid,
+            "Date": format_date(&self.created_at),
+            "IpAddress": self.ip_address,
+            "DeviceType": self.device_type,
+            "DeviceTypeName": self.device_type.map(Device::type_name),
+            "DeviceName": self.device_name,
+            "GrantType": self.grant_type,
+            "TwoFactorType": self.twofactor_type,
+            "TwoFactorName": self.twofactor_type.map(TwoFactorType::display_name),
+            "Success": self.success,
+            "FailureReason": self.failure_reason,
+            "Object": "loginHistory",
+        })
+    }
+}
+
+/// Database methods
+impl LoginHistory {
+    /// Stores the entry and applies the per-user limit. The old entries are removed periodically instead
+    pub fn save(&self, conn: &DbConn) -> EmptyResult {
+        self.insert(conn)?;
+
+        let max_entries = CONFIG.login_history_max_entries();
+        if max_entries > 0 {
+            Self::truncate_by_user(&self.user_uuid, self.success, i64::from(max_entries), conn)?;
+        }
+        Ok(())
+    }
+
+    fn insert(&self, conn: &DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::insert_into(login_history::table)
+                .values(LoginHistoryDb::to_db(self))
+                .execute(conn)
+                .map_res("Error saving login history")
+        }}
+    }
+
+    pub fn delete_all_by_user(user_uuid: &str, conn: &DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(login_history::table.filter(login_history::user_uuid.eq(user_uuid)))
+                .execute(conn)
+                .map_res("Error deleting login history")
+        }}
+    }
+
+    pub fn delete_older_than(days: i64, conn: &DbConn) -> EmptyResult {
+        let oldest = Utc::now().naive_utc() - Duration::days(days);
+        db_run! { conn: {
+            diesel::delete(login_history::table.filter(login_history::created_at.lt(oldest)))
+                .execute(conn)
+                .map_res("Error deleting old login history")
+        }}
+    }
+
+    /// Keeps only the newest `max_entries` successful or failed entries of the user.
+    /// They are limited separately, so many failed attempts can't push the successful logins out of the history
+    fn truncate_by_user(user_uuid: &str, success: bool, max_entries: i64, conn: &DbConn) -> EmptyResult {
+        // MySQL doesn't support LIMIT in subqueries, so find the date of the oldest entry to keep first
+        let oldest_kept: Option<NaiveDateTime> = db_run! { conn: {
+            login_history::table
+                .filter(login_history::user_uuid.eq(user_uuid))
+                .filter(login_history::success.eq(success))
+                .select(login_history::created_at)
+                .order(login_history::created_at.desc())
+                .offset(max_entries - 1)
+                .first::<NaiveDateTime>(conn)
+                .ok()
+        }};
+
+        match oldest_kept {
+            Some(oldest) => db_run! { conn: {
+                diesel::delete(
+                    login_history::table
+                        .filter(login_history::user_uuid.eq(user_uuid))
+                        .filter(login_history::success.eq(success))
+                        .filter(login_history::created_at.lt(oldest)),
+                )
+                .execute(conn)
+                .map_res("Error truncating login history")
+            }},
+            None => Ok(()),
+        }
+    }
+
+    pub fn find_by_user(user_uuid: &str, conn: &DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            login_history::table
+                .filter(login_history::user_uuid.eq(user_uuid))
+                .order(login_history::created_at.desc())
+                .load::<LoginHistoryDb>(conn)
+                .expect("Error loading login history")
+                .from_db()
+        }}
+    }
+}
@@ -0,0 +1,292 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde_json::Value;
+
+use crate::api::EmptyResult;
+use crate::db::DbConn;
+use crate::error::{Error, MapResult};
+use crate::util::format_naive_datetime_local;
+
+db_object! {
+    #[derive(Debug, Identifiable, Queryable, Insertable, AsChangeset)]
+    #[table_name = "mail_queue"]
+    #[changeset_options(treat_none_as_null="true")]
+    #[primary_key(uuid)]
+    pub struct QueuedMail {
+        pub uuid: String,
+        pub address: String,
+        pub subject: String,
+        pub body_html: String,
+        pub body_text: String,
+        pub created_at: NaiveDateTime,
+        pub status: i32,
+        pub attempts: i32,
+        pub next_attempt_at: NaiveDateTime,
+        pub last_error: Option<String>,
+    }
+}
+
+pub enum QueuedMailStatus {
+    Pending = 0,
+    Sending = 1,
+    Failed = 2,
+    Sent = 3, // Delivered, but it couldn't be deleted right away
+}
+
+/// Local methods
+impl QueuedMail {
+    pub fn new(address: String, subject: String, body_html: String, body_text: String) -> Self {
+        let now = Utc::now().naive_utc();
+
+        Self {
+            uuid: crate::util::get_uuid(),
+            address,
+            subject,
+            body_html,
+            body_text,
+            created_at: now,
+            status: QueuedMailStatus::Pending as i32,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+        }
+    }
+
+    /// Registers a failed attempt, scheduling a new one with an exponential delay,
+    /// or marking the mail as failed once the maximum number of attempts is reached
+    pub fn attempt_failed(&mut self, error: String, max_attempts: i32, base_delay: i64) {
+        self.attempts += 1;
+        self.last_error = Some(error);
+
+        if self.attempts >= max_attempts {
+            self.status = QueuedMailStatus::Failed as i32;
+        } else {
+            // Cap the exponent to avoid overflows, at that point the delay is already very long
+            let delay = base_delay.saturating_mul(1 << (self.attempts - 1).min(16));
+            self.status = QueuedMailStatus::Pending as i32;
+            self.next_attempt_at = Utc::now().naive_utc() + Duration::seconds(delay);
+        }
+    }
+
+    /// Used when the mail can't be deleted after sending it, so it's not sent again.
+    /// The mails contain login codes and tokens, so their content is removed too
+    pub fn mark_sent(&mut self) {
+        self.status = QueuedMailStatus::Sent as i32;
+        self.body_html.clear();
+        self.body_text.clear();
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.status == QueuedMailStatus::Failed as i32
+    }
+
+    /// Schedules the mail to be sent again as soon as possible, used to manually retry the failed mails
+    /// and the ones waiting for their next attempt
+    pub fn reset(&mut self) {
+        self.status = QueuedMailStatus::Pending as i32;
+        self.attempts = 0;
+        self.next_attempt_at = Utc::now().naive_utc();
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "Id": self.uuid,
+            "Address": self.address,
+            "Subject": self.subject,
+            "CreatedAt": format_naive_datetime_local(&self.created_at, "%Y-%m-%d %H:%M:%S %Z"),
+            "Attempts": self.attempts,
+            "LastError": self.last_error,
+            "Failed": self.is_failed(),
+        })
+    }
+}
+
+/// Database methods
+impl QueuedMail {
+    pub fn save(&self, conn: &DbConn) -> EmptyResult {
+        db_run! { conn:
+            sqlite, mysql {
+                crate::util::retry(
+                    || diesel::replace_into(mail_queue::table).values(QueuedMailDb::to_db(self)).execute(conn),
+                    10,
+                ).map_res("Error saving queued mail")
+            }
+            postgresql {
+                let value = QueuedMailDb::to_db(self);
+                crate::util::retry(
+                    || diesel::insert_into(mail_queue::table).values(&value).on_conflict(mail_queue::uuid).do_update().set(&value).execute(conn),
+                    10,
+                ).map_res("Error saving queued mail")
+            }
+        }
+    }
+
+    pub fn delete(&self, conn: &DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(mail_queue::table.filter(mail_queue::uuid.eq(&self.uuid)))
+                .execute(conn)
+                .map_res("Error deleting queued mail")
+        }}
+    }
+
+    /// Marks a pending mail as being sent. Returns false if it was claimed by another worker in the meantime
+    pub fn claim(&mut self, conn: &DbConn) -> Result<bool, Error> {
+        let claimed: Result<usize, Error> = db_run! { conn: {
+            diesel::update(
+                mail_queue::table
+                    .filter(mail_queue::uuid.eq(&self.uuid))
+                    .filter(mail_queue::status.eq(QueuedMailStatus::Pending as i32)),
+            )
+            .set(mail_queue::status.eq(QueuedMailStatus::Sending as i32))
+            .execute(conn)
+            .map_res("Error claiming queued mail")
+        }};
+
+        if claimed? == 1 {
+            self.status = QueuedMailStatus::Sending as i32;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Mails left in the sending state when the server stopped are scheduled again
+    pub fn reset_interrupted(conn: &DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::update(mail_queue::table.filter(mail_queue::status.eq(QueuedMailStatus::Sending as i32)))
+                .set(mail_queue::status.eq(QueuedMailStatus::Pending as i32))
+                .execute(conn)
+                .map_res("Error resetting interrupted mails")
+                .map(|_| ())
+        }}
+    }
+
+    /// Removes the mails that were sent but couldn't be deleted.
+    /// The failed ones are kept until they are resent or dismissed from the admin page
+    pub fn delete_sent(conn: &DbConn) -> EmptyResult {
+        db_run! { conn: {
+            diesel::delete(mail_queue::table.filter(mail_queue::status.eq(QueuedMailStatus::Sent as i32)))
+                .execute(conn)
+                .map_res("Error deleting sent mails")
+        }}
+    }
+
+    pub fn find_by_uuid(uuid: &str, conn: &DbConn) -> Option<Self> {
+        db_run! { conn: {
+            mail_queue::table
+                .filter(mail_queue::uuid.eq(uuid))
+                .first::<QueuedMailDb>(conn)
+                .ok()
+                .from_db()
+        }}
+    }
+
+    pub fn find_due(limit: i64, conn: &DbConn) -> Result<Vec<Self>, Error> {
+        let now = Utc::now().naive_utc();
+        db_run! { conn: {
+            Ok(mail_queue::table
+                .filter(mail_queue::status.eq(QueuedMailStatus::Pending as i32))
+                .filter(mail_queue::next_attempt_at.le(now))
+                .order(mail_queue::next_attempt_at.asc())
+                .limit(limit)
+                .load::<QueuedMailDb>(conn)
+                .map_res("Error loading queued mails")?
+                .from_db())
+        }}
+    }
+
+    /// Finds the failed mails, and the ones waiting to be retried after a failed attempt
+    pub fn find_failed(conn: &DbConn) -> Vec<Self> {
+        db_run! { conn: {
+            mail_queue::table
+                .filter(
+                    mail_queue::status.eq(QueuedMailStatus::Failed as i32).or(mail_queue::status
+                        .eq(QueuedMailStatus::Pending as i32)
+                        .and(mail_queue::attempts.gt(0))),
+                )
+                .order(mail_queue::created_at.desc())
+                .load::<QueuedMailDb>(conn)
+                .expect("Error loading failed mails")
+                .from_db()
+        }}
+    }
+
+    pub fn count_pending(conn: &DbConn) -> i64 {
+        db_run! { conn: {
+            mail_queue::table
+                .filter(mail_queue::status.eq_any(vec![QueuedMailStatus::Pending as i32, QueuedMailStatus::Sending as i32]))
+                .count()
+                .first::<i64>(conn)
+                .ok()
+                .unwrap_or(0)
+        }}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued_mail() -> QueuedMail {
+        QueuedMail::new(
+            "user@example.com".to_string(),
+            "Your two-step login code".to_string(),
+            "<p>123456</p>".to_string(),
+            "123456".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_attempt_failed_retries_with_exponential_delay() {
+        let mut mail = queued_mail();
+
+        mail.attempt_failed("Connection refused".to_string(), 3, 60);
+        assert!(!mail.is_failed());
+        assert_eq!(mail.attempts, 1);
+        let delay = mail.next_attempt_at.signed_duration_since(Utc::now().naive_utc()).num_seconds();
+        assert!((58..=60).contains(&delay));
+
+        mail.attempt_failed("Connection refused".to_string(), 3, 60);
+        assert!(!mail.is_failed());
+        let delay = mail.next_attempt_at.signed_duration_since(Utc::now().naive_utc()).num_seconds();
+        assert!((118..=120).contains(&delay));
+        assert_eq!(mail.body_text, "123456");
+    }
+
+    #[test]
+    fn test_attempt_failed_keeps_body_after_last_attempt() {
+        let mut mail = queued_mail();
+
+        for _ in 0..3 {
+            mail.attempt_failed("Connection refused".to_string(), 3, 60);
+        }
+        assert!(mail.is_failed());
+        assert_eq!(mail.attempts, 3);
+        assert_eq!(mail.last_error.as_deref(), Some("Connection refused"));
+        assert_eq!(mail.body_text, "123456");
+
+        // The failed mails can be resent from the admin page
+        mail.reset();
+        assert!(!mail.is_failed());
+        assert_eq!(mail.attempts, 0);
+    }
+
+    #[test]
+    fn test_attempt_failed_caps_delay() {
+        let mut mail = queued_mail();
+        mail.attempts = 40;
+
+        mail.attempt_failed("Timeout".to_string(), 100, 60);
+        assert!(!mail.is_failed());
+        let delay = mail.next_attempt_at.signed_duration_since(Utc::now().naive_utc()).num_seconds();
+        assert!((60 * 65_535..=60 * 65_536).contains(&delay));
+    }
+
+    #[test]
+    fn test_mark_sent_clears_body() {
+        let mut mail = queued_mail();
+        mail.mark_sent();
+
+        assert_eq!(mail.status, QueuedMailStatus::Sent as i32);
+        assert!(mail.body_html.is_empty() && mail.body_text.is_empty());
+    }
+}
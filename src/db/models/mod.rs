@@ -4,18 +4,24 @@ mod collection;
 mod device;
 mod favorite;
 mod folder;
+mod hibp_cache;
+mod login_history;
+mod mail_queue;
 mod org_policy;
 mod organization;
 mod two_factor;
 mod user;
 
 pub use self::attachment::Attachment;
-pub use self::cipher::Cipher;
+pub use self::cipher::{Cipher, CipherSyncData};
 pub use self::collection::{Collection, CollectionCipher, CollectionUser};
 pub use self::device::Device;
 pub use self::favorite::Favorite;
 pub use self::folder::{Folder, FolderCipher};
+pub use self::hibp_cache::HibpCache;
+pub use self::login_history::LoginHistory;
+pub use self::mail_queue::QueuedMail;
 pub use self::org_policy::{OrgPolicy, OrgPolicyType};
 pub use self::organization::{Organization, UserOrgStatus, UserOrgType, UserOrganization};
 pub use self::two_factor::{TwoFactor, TwoFactorType};
-pub use self::user::{Invitation, User, UserStampException};
+pub use self::user::{Invitation, User, UserKdfType, UserStampException};
@@ -38,6 +38,22 @@ pub enum TwoFactorType {
     EmailVerificationChallenge = 1002,
 }
 
+impl TwoFactorType {
+    /// Name of the two-step login method, as shown to the users
+    pub fn display_name(atype: i32) -> &'static str {
+        use num_traits::FromPrimitive;
+
+        match TwoFactorType::from_i32(atype) {
+            Some(TwoFactorType::Authenticator) => "Authenticator app",
+            Some(TwoFactorType::Email) => "Email",
+            Some(TwoFactorType::Duo) | Some(TwoFactorType::OrganizationDuo) => "Duo",
+            Some(TwoFactorType::YubiKey) => "YubiKey OTP security key",
+            Some(TwoFactorType::U2f) => "FIDO U2F security key",
+            _ => "Unknown method",
+        }
+    }
+}
+
 /// Local methods
 impl TwoFactor {
     pub fn new(user_uuid: String, atype: TwoFactorType, data: String) -> Self {
@@ -1,4 +1,5 @@
 use chrono::{NaiveDateTime, Utc};
+use num_traits::FromPrimitive;
 use serde_json::Value;
 
 use crate::crypto;
@@ -26,6 +27,7 @@ db_object! {
         pub password_hash: Vec<u8>,
         pub salt: Vec<u8>,
         pub password_iterations: i32,
+        pub password_algorithm: i32,
         pub password_hint: Option<String>,
 
         pub akey: String,
@@ -44,6 +46,13 @@ db_object! {
 
         pub client_kdf_type: i32,
         pub client_kdf_iter: i32,
+        pub client_kdf_memory: Option<i32>,
+        pub client_kdf_parallelism: Option<i32>,
+
+        pub culture: String,
+
+        pub security_notifications: bool,
+        pub failed_twofactor_attempts: i32,
     }
 
 
@@ -55,6 +64,28 @@ db_object! {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum UserKdfType {
+    Pbkdf2 = 0,
+    Argon2id = 1,
+}
+
+impl UserKdfType {
+    pub fn display_name(self) -> &'static str {
+        match self {
+            UserKdfType::Pbkdf2 => "PBKDF2-SHA256",
+            UserKdfType::Argon2id => "Argon2id",
+        }
+    }
+}
+
+/// Algorithm used to hash the master password on the server
+#[derive(Copy, Clone, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum PasswordHashType {
+    Pbkdf2 = 0,
+    Argon2id = 1,
+}
+
 enum UserStatus {
     Enabled = 0,
     Invited = 1,
@@ -69,9 +100,18 @@ pub struct UserStampException {
 
 /// Local methods
 impl User {
-    pub const CLIENT_KDF_TYPE_DEFAULT: i32 = 0; // PBKDF2: 0
+    pub const CLIENT_KDF_TYPE_DEFAULT: i32 = UserKdfType::Pbkdf2 as i32;
     pub const CLIENT_KDF_ITER_DEFAULT: i32 = 100_000;
 
+    // Minimum values accepted for the client KDF settings, the Argon2id memory is in MiB
+    pub const CLIENT_KDF_PBKDF2_ITER_MIN: i32 = 100_000;
+    pub const CLIENT_KDF_ARGON2_ITER_MIN: i32 = 2;
+    pub const CLIENT_KDF_ARGON2_MEMORY_MIN: i32 = 15;
+    pub const CLIENT_KDF_ARGON2_MEMORY_MAX: i32 = 1024;
+    pub const CLIENT_KDF_ARGON2_PARALLELISM_MIN: i32 = 1;
+    pub const CLIENT_KDF_ARGON2_PARALLELISM_MAX: i32 = 16;
+    pub const CULTURE_DEFAULT: &'static str = "en-US";
+
     pub fn new(mail: String) -> Self {
         let now = Utc::now().naive_utc();
         let email = mail.to_lowercase();
@@ -93,6 +133,7 @@ impl User {
             password_hash: Vec::new(),
             salt: crypto::get_random_64(),
             password_iterations: CONFIG.password_iterations(),
+            password_algorithm: PasswordHashType::Pbkdf2 as i32,
 
             security_stamp: crate::util::get_uuid(),
             stamp_exception: None,
@@ -109,16 +150,82 @@ impl User {
 
             client_kdf_type: Self::CLIENT_KDF_TYPE_DEFAULT,
             client_kdf_iter: Self::CLIENT_KDF_ITER_DEFAULT,
+            client_kdf_memory: None,
+            client_kdf_parallelism: None,
+
+            culture: Self::CULTURE_DEFAULT.to_string(),
+
+            security_notifications: true,
+            failed_twofactor_attempts: 0,
         }
     }
 
     pub fn check_valid_password(&self, password: &str) -> bool {
-        crypto::verify_password_hash(
-            password.as_bytes(),
-            &self.salt,
-            &self.password_hash,
-            self.password_iterations as u32,
-        )
+        match PasswordHashType::from_i32(self.password_algorithm) {
+            Some(PasswordHashType::Pbkdf2) => crypto::verify_password_hash(
+                password.as_bytes(),
+                &self.salt,
+                &self.password_hash,
+                self.password_iterations as u32,
+            ),
+            Some(PasswordHashType::Argon2id) => {
+                crypto::verify_password_argon2(password.as_bytes(), &self.password_hash)
+            }
+            None => false,
+        }
+    }
+
+    /// Checks if the password hash was generated with different settings than the current ones
+    pub fn password_needs_rehash(&self) -> bool {
+        let use_argon2 = CONFIG.password_hash_algorithm() == "argon2id";
+        match PasswordHashType::from_i32(self.password_algorithm) {
+            Some(PasswordHashType::Pbkdf2) => use_argon2 || self.password_iterations < CONFIG.password_iterations(),
+            Some(PasswordHashType::Argon2id) => {
+                !use_argon2
+                    || !crypto::argon2_params_match(
+                        &self.password_hash,
+                        CONFIG.password_argon2_memory(),
+                        CONFIG.password_argon2_iterations(),
+                        CONFIG.password_argon2_parallelism(),
+                    )
+            }
+            None => false,
+        }
+    }
+
+    /// Hashes the password again with the current settings, without changing the security stamp.
+    /// The password must have been validated before.
+    pub fn rehash_password(&mut self, password: &str) {
+        self.hash_password(password);
+    }
+
+    fn hash_password(&mut self, password: &str) {
+        if CONFIG.password_hash_algorithm() == "argon2id" {
+            match crypto::hash_password_argon2(
+                password.as_bytes(),
+                &self.salt,
+                CONFIG.password_argon2_memory(),
+                CONFIG.password_argon2_iterations(),
+                CONFIG.password_argon2_parallelism(),
+            ) {
+                Ok(hash) => {
+                    self.password_hash = hash;
+                    self.password_algorithm = PasswordHashType::Argon2id as i32;
+                    return;
+                }
+                Err(e) => error!("Error hashing the password with Argon2id, using PBKDF2 instead: {:?}", e),
+            }
+        }
+
+        self.password_iterations = CONFIG.password_iterations();
+        self.password_hash = crypto::hash_password(password.as_bytes(), &self.salt, self.password_iterations as u32);
+        self.password_algorithm = PasswordHashType::Pbkdf2 as i32;
+    }
+
+    /// Hashes the password with the current settings and discards the result. Used when there's no account,
+    /// so those requests take about as long as the ones that check or set a real password
+    pub fn hash_dummy_password(password: &str) {
+        Self::new(String::new()).hash_password(password);
     }
 
     pub fn check_valid_recovery_code(&self, recovery_code: &str) -> bool {
@@ -138,7 +245,7 @@ impl User {
     /// * `allow_next_route` - A Option<&str> with the function name of the next allowed (rocket) route.
     ///
     pub fn set_password(&mut self, password: &str, allow_next_route: Option<&str>) {
-        self.password_hash = crypto::hash_password(password.as_bytes(), &self.salt, self.password_iterations as u32);
+        self.hash_password(password);
 
         if let Some(route) = allow_next_route {
             self.set_stamp_exception(route);
@@ -175,9 +282,24 @@ impl User {
     pub fn reset_stamp_exception(&mut self) {
         self.stamp_exception = None;
     }
+
+    /// Stores the language sent by the clients, used to select the translated email templates.
+    /// Values that don't look like a language tag (e.g. `de`, `pt-BR`) are ignored.
+    pub fn set_culture(&mut self, culture: &str) {
+        let culture = culture.trim();
+        let mut parts = culture.split('-');
+
+        let valid_language = matches!(parts.next(), Some(l) if (2..=3).contains(&l.len())
+            && l.chars().all(|c| c.is_ascii_alphabetic()));
+        let valid_subtags = parts.all(|p| (1..=8).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        if valid_language && valid_subtags && culture.len() <= 35 {
+            self.culture = culture.to_string();
+        }
+    }
 }
 
-use super::{Cipher, Device, Favorite, Folder, TwoFactor, UserOrgType, UserOrganization};
+use super::{Cipher, Device, Favorite, Folder, LoginHistory, TwoFactor, UserOrgType, UserOrganization};
 use crate::db::DbConn;
 
 use crate::api::EmptyResult;
@@ -205,7 +327,7 @@ impl User {
             "EmailVerified": !CONFIG.mail_enabled() || self.verified_at.is_some(),
             "Premium": true,
             "MasterPasswordHint": self.password_hint,
-            "Culture": "en-US",
+            "Culture": self.culture,
             "TwoFactorEnabled": twofactor_enabled,
             "Key": self.akey,
             "PrivateKey": self.private_key,
@@ -269,6 +391,7 @@ impl User {
         Folder::delete_all_by_user(&self.uuid, conn)?;
         Device::delete_all_by_user(&self.uuid, conn)?;
         TwoFactor::delete_all_by_user(&self.uuid, conn)?;
+        LoginHistory::delete_all_by_user(&self.uuid, conn)?;
         Invitation::take(&self.email, conn); // Delete invitation if any
 
         db_run! {conn: {
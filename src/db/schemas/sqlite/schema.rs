@@ -3,7 +3,7 @@ table! {
         id -> Text,
         cipher_uuid -> Text,
         file_name -> Text,
-        file_size -> Integer,
+        file_size -> BigInt,
         akey -> Nullable<Text>,
     }
 }
@@ -51,6 +51,9 @@ table! {
         push_token -> Nullable<Text>,
         refresh_token -> Text,
         twofactor_remember -> Nullable<Text>,
+        previous_refresh_token -> Nullable<Text>,
+        session_created_at -> Nullable<Timestamp>,
+        last_refresh_at -> Nullable<Timestamp>,
     }
 }
 
@@ -78,12 +81,50 @@ table! {
     }
 }
 
+table! {
+    hibp_cache (account_hash) {
+        account_hash -> Text,
+        response -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     invitations (email) {
         email -> Text,
     }
 }
 
+table! {
+    login_history (uuid) {
+        uuid -> Text,
+        user_uuid -> Text,
+        created_at -> Timestamp,
+        ip_address -> Text,
+        device_type -> Nullable<Integer>,
+        device_name -> Nullable<Text>,
+        grant_type -> Text,
+        twofactor_type -> Nullable<Integer>,
+        success -> Bool,
+        failure_reason -> Nullable<Text>,
+    }
+}
+
+table! {
+    mail_queue (uuid) {
+        uuid -> Text,
+        address -> Text,
+        subject -> Text,
+        body_html -> Text,
+        body_text -> Text,
+        created_at -> Timestamp,
+        status -> Integer,
+        attempts -> Integer,
+        next_attempt_at -> Timestamp,
+        last_error -> Nullable<Text>,
+    }
+}
+
 table! {
     org_policies (uuid) {
         uuid -> Text,
@@ -129,6 +170,7 @@ table! {
         password_hash -> Binary,
         salt -> Binary,
         password_iterations -> Integer,
+        password_algorithm -> Integer,
         password_hint -> Nullable<Text>,
         akey -> Text,
         private_key -> Nullable<Text>,
@@ -141,6 +183,11 @@ table! {
         excluded_globals -> Text,
         client_kdf_type -> Integer,
         client_kdf_iter -> Integer,
+        client_kdf_memory -> Nullable<Integer>,
+        client_kdf_parallelism -> Nullable<Integer>,
+        culture -> Text,
+        security_notifications -> Bool,
+        failed_twofactor_attempts -> Integer,
     }
 }
 
@@ -175,6 +222,7 @@ joinable!(devices -> users (user_uuid));
 joinable!(folders -> users (user_uuid));
 joinable!(folders_ciphers -> ciphers (cipher_uuid));
 joinable!(folders_ciphers -> folders (folder_uuid));
+joinable!(login_history -> users (user_uuid));
 joinable!(org_policies -> organizations (org_uuid));
 joinable!(twofactor -> users (user_uuid));
 joinable!(users_collections -> collections (collection_uuid));
@@ -190,7 +238,10 @@ allow_tables_to_appear_in_same_query!(
     devices,
     folders,
     folders_ciphers,
+    hibp_cache,
     invitations,
+    login_history,
+    mail_queue,
     org_policies,
     organizations,
     twofactor,
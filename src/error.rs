@@ -33,12 +33,15 @@ macro_rules! make_error {
     };
 }
 
+use argon2::Error as Argon2Err;
 use diesel::result::Error as DieselErr;
 use diesel::ConnectionError as DieselConErr;
 use diesel_migrations::RunMigrationsError as DieselMigErr;
 use diesel::r2d2::PoolError as R2d2Err;
 use handlebars::RenderError as HbErr;
+use image::ImageError as ImageErr;
 use jsonwebtoken::errors::Error as JWTErr;
+use openssl::error::ErrorStack as SslErr;
 use regex::Error as RegexErr;
 use reqwest::Error as ReqErr;
 use serde_json::{Error as SerdeErr, Value};
@@ -80,6 +83,9 @@ make_error! {
     ReqError(ReqErr):     _has_source, _api_error,
     RegexError(RegexErr): _has_source, _api_error,
     YubiError(YubiErr):   _has_source, _api_error,
+    SslError(SslErr):     _has_source, _api_error,
+    ImageError(ImageErr): _has_source, _api_error,
+    Argon2Error(Argon2Err): _has_source, _api_error,
 
     LettreError(LettreErr):   _has_source, _api_error,
     AddressError(AddrErr):    _has_source, _api_error,
@@ -1,6 +1,20 @@
-use std::{str::FromStr};
+use std::{
+    path::Path,
+    str::FromStr,
+    sync::{Condvar, Mutex},
+    thread,
+    time::Duration,
+};
 
 use chrono::{DateTime, Local};
+use data_encoding::BASE64;
+use once_cell::sync::{Lazy, OnceCell};
+use openssl::{
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    sign::Signer,
+};
+use ring::digest;
 use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
 
 use lettre::{
@@ -8,18 +22,28 @@ use lettre::{
     transport::smtp::authentication::{Credentials, Mechanism as SmtpAuthMechanism},
     transport::smtp::client::{Tls, TlsParameters},
     transport::smtp::extension::ClientId,
-    Address, SmtpTransport, Transport,
+    address::Envelope,
+    Address, SendmailTransport, SmtpTransport, Transport,
 };
 
 use crate::{
     api::EmptyResult,
-    auth::{encode_jwt, generate_delete_claims, generate_invite_claims, generate_verify_email_claims},
+    auth::{
+        encode_jwt, generate_delete_claims, generate_invite_claims, generate_notifications_claims,
+        generate_verify_email_claims,
+    },
+    db::{
+        models::{QueuedMail, User, UserKdfType},
+        DbConn, DbPool,
+    },
     error::Error,
+    util::read_file,
     CONFIG,
 };
 
+const DATETIME_FORMAT: &str = "%A, %B %_d, %Y at %r %Z";
+
 fn mailer() -> SmtpTransport {
-    use std::time::Duration;
     let host = CONFIG.smtp_host().unwrap();
 
     let smtp_client = SmtpTransport::builder_dangerous(host.as_str())
@@ -82,12 +106,33 @@ fn mailer() -> SmtpTransport {
     smtp_client.build()
 }
 
-fn get_text(template_name: &'static str, data: serde_json::Value) -> Result<(String, String, String), Error> {
+fn get_text(template_name: &str, culture: &str, data: serde_json::Value) -> Result<(String, String, String), Error> {
+    let template_name = localized_template_name(template_name, culture);
     let (subject_html, body_html) = get_template(&format!("{}.html", template_name), &data)?;
-    let (_subject_text, body_text) = get_template(template_name, &data)?;
+    let (_subject_text, body_text) = get_template(&template_name, &data)?;
     Ok((subject_html, body_html, body_text))
 }
 
+/// Finds the translation of a template for the given culture, first trying the full culture (`pt-br`) and
+/// then only the language (`pt`). The text and HTML versions must both exist, otherwise English is used.
+fn localized_template_name(template_name: &str, culture: &str) -> String {
+    let culture = culture.to_lowercase();
+    let language = culture.split('-').next().unwrap_or_default();
+
+    for locale in &[culture.as_str(), language] {
+        if locale.is_empty() {
+            continue;
+        }
+
+        let localized = format!("{}.{}", template_name, locale);
+        if CONFIG.has_template(&localized) && CONFIG.has_template(&format!("{}.html", localized)) {
+            return localized;
+        }
+    }
+
+    template_name.to_string()
+}
+
 fn get_template(template_name: &str, data: &serde_json::Value) -> Result<(String, String), Error> {
     let text = CONFIG.render_template(template_name, data)?;
     let mut text_split = text.split("<!---------------->");
@@ -106,24 +151,26 @@ fn get_template(template_name: &str, data: &serde_json::Value) -> Result<(String
     Ok((subject, body))
 }
 
-pub fn send_password_hint(address: &str, hint: Option<String>) -> EmptyResult {
+pub fn send_password_hint(address: &str, hint: Option<String>, culture: &str) -> EmptyResult {
     let template_name = if hint.is_some() {
         "email/pw_hint_some"
     } else {
         "email/pw_hint_none"
     };
 
-    let (subject, body_html, body_text) = get_text(template_name, json!({ "hint": hint, "url": CONFIG.domain() }))?;
+    let (subject, body_html, body_text) =
+        get_text(template_name, culture, json!({ "hint": hint, "url": CONFIG.domain() }))?;
 
     send_email(address, &subject, body_html, body_text)
 }
 
-pub fn send_delete_account(address: &str, uuid: &str) -> EmptyResult {
+pub fn send_delete_account(address: &str, uuid: &str, culture: &str) -> EmptyResult {
     let claims = generate_delete_claims(uuid.to_string());
     let delete_token = encode_jwt(&claims);
 
     let (subject, body_html, body_text) = get_text(
         "email/delete_account",
+        culture,
         json!({
             "url": CONFIG.domain(),
             "user_id": uuid,
@@ -135,12 +182,13 @@ pub fn send_delete_account(address: &str, uuid: &str) -> EmptyResult {
     send_email(address, &subject, body_html, body_text)
 }
 
-pub fn send_verify_email(address: &str, uuid: &str) -> EmptyResult {
+pub fn send_verify_email(address: &str, uuid: &str, culture: &str) -> EmptyResult {
     let claims = generate_verify_email_claims(uuid.to_string());
     let verify_email_token = encode_jwt(&claims);
 
     let (subject, body_html, body_text) = get_text(
         "email/verify_email",
+        culture,
         json!({
             "url": CONFIG.domain(),
             "user_id": uuid,
@@ -152,9 +200,23 @@ pub fn send_verify_email(address: &str, uuid: &str) -> EmptyResult {
     send_email(address, &subject, body_html, body_text)
 }
 
-pub fn send_welcome(address: &str) -> EmptyResult {
+/// Sent instead of an error when someone tries to register an email that already has an account
+pub fn send_account_exists(address: &str, culture: &str) -> EmptyResult {
+    let (subject, body_html, body_text) = get_text(
+        "email/account_exists",
+        culture,
+        json!({
+            "url": CONFIG.domain(),
+        }),
+    )?;
+
+    send_email(address, &subject, body_html, body_text)
+}
+
+pub fn send_welcome(address: &str, culture: &str) -> EmptyResult {
     let (subject, body_html, body_text) = get_text(
         "email/welcome",
+        culture,
         json!({
             "url": CONFIG.domain(),
         }),
@@ -163,12 +225,13 @@ pub fn send_welcome(address: &str) -> EmptyResult {
     send_email(address, &subject, body_html, body_text)
 }
 
-pub fn send_welcome_must_verify(address: &str, uuid: &str) -> EmptyResult {
+pub fn send_welcome_must_verify(address: &str, uuid: &str, culture: &str) -> EmptyResult {
     let claims = generate_verify_email_claims(uuid.to_string());
     let verify_email_token = encode_jwt(&claims);
 
     let (subject, body_html, body_text) = get_text(
         "email/welcome_must_verify",
+        culture,
         json!({
             "url": CONFIG.domain(),
             "user_id": uuid,
@@ -186,6 +249,7 @@ pub fn send_invite(
     org_user_id: Option<String>,
     org_name: &str,
     invited_by_email: Option<String>,
+    culture: &str,
 ) -> EmptyResult {
     let claims = generate_invite_claims(
         uuid.to_string(),
@@ -198,6 +262,7 @@ pub fn send_invite(
 
     let (subject, body_html, body_text) = get_text(
         "email/send_org_invite",
+        culture,
         json!({
             "url": CONFIG.domain(),
             "org_id": org_id.as_deref().unwrap_or("_"),
@@ -211,9 +276,10 @@ pub fn send_invite(
     send_email(address, &subject, body_html, body_text)
 }
 
-pub fn send_invite_accepted(new_user_email: &str, address: &str, org_name: &str) -> EmptyResult {
+pub fn send_invite_accepted(new_user_email: &str, address: &str, org_name: &str, culture: &str) -> EmptyResult {
     let (subject, body_html, body_text) = get_text(
         "email/invite_accepted",
+        culture,
         json!({
             "url": CONFIG.domain(),
             "email": new_user_email,
@@ -224,9 +290,10 @@ pub fn send_invite_accepted(new_user_email: &str, address: &str, org_name: &str)
     send_email(address, &subject, body_html, body_text)
 }
 
-pub fn send_invite_confirmed(address: &str, org_name: &str) -> EmptyResult {
+pub fn send_invite_confirmed(address: &str, org_name: &str, culture: &str) -> EmptyResult {
     let (subject, body_html, body_text) = get_text(
         "email/invite_confirmed",
+        culture,
         json!({
             "url": CONFIG.domain(),
             "org_name": org_name,
@@ -236,27 +303,39 @@ pub fn send_invite_confirmed(address: &str, org_name: &str) -> EmptyResult {
     send_email(address, &subject, body_html, body_text)
 }
 
-pub fn send_new_device_logged_in(address: &str, ip: &str, dt: &DateTime<Local>, device: &str) -> EmptyResult {
+pub fn send_new_device_logged_in(
+    address: &str,
+    ip: &str,
+    dt: &DateTime<Local>,
+    device: &str,
+    culture: &str,
+) -> EmptyResult {
     use crate::util::upcase_first;
     let device = upcase_first(device);
 
-    let fmt = "%A, %B %_d, %Y at %r %Z";
     let (subject, body_html, body_text) = get_text(
         "email/new_device_logged_in",
+        culture,
         json!({
             "url": CONFIG.domain(),
             "ip": ip,
             "device": device,
-            "datetime": crate::util::format_datetime_local(dt, fmt),
+            "datetime": crate::util::format_datetime_local(dt, DATETIME_FORMAT),
         }),
     )?;
 
+    // The login fails if this email can't be sent, so it can't wait in the queue
+    if CONFIG.require_device_email() {
+        return deliver_email(address, &subject, body_html, body_text);
+    }
+
     send_email(address, &subject, body_html, body_text)
 }
 
-pub fn send_token(address: &str, token: &str) -> EmptyResult {
+pub fn send_token(address: &str, token: &str, culture: &str) -> EmptyResult {
     let (subject, body_html, body_text) = get_text(
         "email/twofactor_email",
+        culture,
         json!({
             "url": CONFIG.domain(),
             "token": token,
@@ -266,9 +345,10 @@ pub fn send_token(address: &str, token: &str) -> EmptyResult {
     send_email(address, &subject, body_html, body_text)
 }
 
-pub fn send_change_email(address: &str, token: &str) -> EmptyResult {
+pub fn send_change_email(address: &str, token: &str, culture: &str) -> EmptyResult {
     let (subject, body_html, body_text) = get_text(
         "email/change_email",
+        culture,
         json!({
             "url": CONFIG.domain(),
             "token": token,
@@ -278,18 +358,239 @@ pub fn send_change_email(address: &str, token: &str) -> EmptyResult {
     send_email(address, &subject, body_html, body_text)
 }
 
+//
+// Security notifications
+//
+// Sent after sensitive changes to the account, they can be disabled globally or by each user with the emailed link.
+// Errors sending them are only logged by the callers, as the change was already made.
+pub fn send_password_changed(user: &User) -> EmptyResult {
+    send_security_notification(&user.email, user, "email/security_password_changed", json!({}))
+}
+
+/// Sent to the previous email of the user, which should be the one still under their control
+pub fn send_email_changed(old_email: &str, user: &User) -> EmptyResult {
+    send_security_notification(
+        old_email,
+        user,
+        "email/security_email_changed",
+        json!({
+            "old_email": old_email,
+            "new_email": user.email,
+        }),
+    )
+}
+
+pub fn send_twofactor_added(user: &User, provider: &str) -> EmptyResult {
+    send_security_notification(
+        &user.email,
+        user,
+        "email/security_twofactor_added",
+        json!({ "provider": provider }),
+    )
+}
+
+pub fn send_twofactor_removed(user: &User, provider: &str) -> EmptyResult {
+    send_security_notification(
+        &user.email,
+        user,
+        "email/security_twofactor_removed",
+        json!({ "provider": provider }),
+    )
+}
+
+pub fn send_kdf_changed(user: &User) -> EmptyResult {
+    use num_traits::FromPrimitive;
+    let kdf = UserKdfType::from_i32(user.client_kdf_type).map_or("an unknown algorithm", UserKdfType::display_name);
+
+    send_security_notification(
+        &user.email,
+        user,
+        "email/security_kdf_changed",
+        json!({
+            "kdf": kdf,
+            "kdf_iterations": user.client_kdf_iter,
+        }),
+    )
+}
+
+pub fn send_key_rotated(user: &User) -> EmptyResult {
+    send_security_notification(&user.email, user, "email/security_key_rotated", json!({}))
+}
+
+pub fn send_failed_twofactor(user: &User, ip: &str) -> EmptyResult {
+    send_security_notification(
+        &user.email,
+        user,
+        "email/security_failed_twofactor",
+        json!({
+            "attempts": user.failed_twofactor_attempts,
+            "ip": ip,
+        }),
+    )
+}
+
+/// The action is one of `disabled`, `twofactor_removed` or `deauthorized`
+pub fn send_admin_action(user: &User, action: &str) -> EmptyResult {
+    send_security_notification(&user.email, user, "email/security_admin_action", json!({ "action": action }))
+}
+
+fn send_security_notification(
+    address: &str,
+    user: &User,
+    template_name: &str,
+    mut data: serde_json::Value,
+) -> EmptyResult {
+    if !CONFIG.mail_enabled() || !CONFIG.security_notifications() || !user.security_notifications {
+        return Ok(());
+    }
+
+    let claims = generate_notifications_claims(user.uuid.clone());
+    data["url"] = json!(CONFIG.domain());
+    data["email"] = json!(address);
+    data["datetime"] = json!(crate::util::format_datetime_local(&Local::now(), DATETIME_FORMAT));
+    data["unsubscribe_url"] = json!(format!(
+        "{}/api/accounts/security-notifications/unsubscribe?token={}",
+        CONFIG.domain(),
+        encode_jwt(&claims)
+    ));
+
+    let (subject, body_html, body_text) = get_text(template_name, &user.culture, data)?;
+    send_email(address, &subject, body_html, body_text)
+}
+
 pub fn send_test(address: &str) -> EmptyResult {
     let (subject, body_html, body_text) = get_text(
         "email/smtp_test",
+        User::CULTURE_DEFAULT,
         json!({
             "url": CONFIG.domain(),
         }),
     )?;
 
-    send_email(address, &subject, body_html, body_text)
+    // Sent directly instead of being queued, so the result of the test is shown right away
+    deliver_email(address, &subject, body_html, body_text)
+}
+
+//
+// Outbound mail queue
+//
+// The emails are stored in the database and sent by background workers, so a slow or unavailable SMTP server
+// doesn't block the requests. Failed deliveries are retried with an exponential delay, and can be resent right away
+// from the admin page. After `MAIL_MAX_ATTEMPTS` they are kept as failed, until they are resent or dismissed there.
+static QUEUE_POOL: OnceCell<DbPool> = OnceCell::new();
+static QUEUE_SIGNAL: Lazy<(Mutex<bool>, Condvar)> = Lazy::new(|| (Mutex::new(false), Condvar::new()));
+
+// Interval to check for mails that need to be retried
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(15);
+const QUEUE_BATCH_SIZE: i64 = 10;
+
+pub fn start_queue_workers(pool: DbPool) {
+    match pool.get() {
+        Ok(conn) => {
+            if let Err(e) = QueuedMail::reset_interrupted(&conn) {
+                error!("Error resetting the mail queue: {:?}", e);
+            }
+        }
+        Err(e) => error!("Error resetting the mail queue: {:?}", e),
+    }
+
+    for i in 0..CONFIG.mail_queue_workers() {
+        let pool = pool.clone();
+        thread::Builder::new()
+            .name(format!("mail-worker-{}", i))
+            .spawn(move || loop {
+                process_queue(&pool);
+                wait_for_mails();
+            })
+            .expect("Error starting mail queue worker");
+    }
+
+    QUEUE_POOL.set(pool).ok();
+}
+
+/// Wakes up the workers to send the queued mails
+pub fn notify_queue() {
+    let (pending, signal) = &*QUEUE_SIGNAL;
+    *pending.lock().unwrap() = true;
+    signal.notify_all();
+}
+
+fn wait_for_mails() {
+    let (pending, signal) = &*QUEUE_SIGNAL;
+    let guard = pending.lock().unwrap();
+    let (mut guard, _) = signal.wait_timeout_while(guard, QUEUE_POLL_INTERVAL, |pending| !*pending).unwrap();
+    *guard = false;
+}
+
+fn process_queue(pool: &DbPool) {
+    if !CONFIG.mail_enabled() {
+        return;
+    }
+
+    let conn = match pool.get() {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Error processing the mail queue: {:?}", e);
+            return;
+        }
+    };
+
+    // Stop on database errors, the workers try again after the poll interval
+    if let Err(e) = send_queued_mails(&conn) {
+        error!("Error processing the mail queue: {:?}", e);
+    }
 }
 
+fn send_queued_mails(conn: &DbConn) -> EmptyResult {
+    QueuedMail::delete_sent(conn)?;
+
+    loop {
+        let mails = QueuedMail::find_due(QUEUE_BATCH_SIZE, conn)?;
+        if mails.is_empty() {
+            return Ok(());
+        }
+
+        for mut mail in mails {
+            // Another worker might have taken it already
+            if !mail.claim(conn)? {
+                continue;
+            }
+
+            match deliver_email(&mail.address, &mail.subject, mail.body_html.clone(), mail.body_text.clone()) {
+                Ok(()) => {
+                    if let Err(e) = crate::util::retry(|| mail.delete(conn), 3) {
+                        // Otherwise it would stay in the sending state, and be sent again after a restart
+                        error!("Error deleting sent email, marking it as sent: {:?}", e);
+                        mail.mark_sent();
+                        mail.save(conn)?;
+                    }
+                }
+                Err(e) => {
+                    mail.attempt_failed(e.to_string(), CONFIG.mail_max_attempts() as i32, CONFIG.mail_retry_delay() as i64);
+                    if mail.is_failed() {
+                        error!("Giving up sending email to {} after {} attempts", mail.address, mail.attempts);
+                    }
+                    mail.save(conn)?;
+                }
+            }
+        }
+    }
+}
+
+/// Adds the email to the queue, it's sent directly if the queue is not running
 fn send_email(address: &str, subject: &str, body_html: String, body_text: String) -> EmptyResult {
+    let pool = match QUEUE_POOL.get() {
+        Some(pool) => pool,
+        None => return deliver_email(address, subject, body_html, body_text),
+    };
+
+    let mail = QueuedMail::new(address.to_string(), subject.to_string(), body_html, body_text);
+    mail.save(&pool.get()?)?;
+    notify_queue();
+    Ok(())
+}
+
+fn deliver_email(address: &str, subject: &str, body_html: String, body_text: String) -> EmptyResult {
     let address_split: Vec<&str> = address.rsplitn(2, '@').collect();
     if address_split.len() != 2 {
         err!("Invalid email address (no @)");
@@ -329,7 +630,20 @@ fn send_email(address: &str, subject: &str, body_html: String, body_text: String
                 .singlepart(html)
         )?;
 
-    match mailer().send(&email) {
+    let raw_email = match dkim_config() {
+        Some(dkim) => dkim_sign(&email.formatted(), &dkim)?,
+        None => email.formatted(),
+    };
+
+    match CONFIG.mail_transport().as_str() {
+        "sendmail" => send_with_sendmail(email.envelope(), &raw_email),
+        "file" => save_to_file(&raw_email),
+        _ => send_with_smtp(email.envelope(), &raw_email),
+    }
+}
+
+fn send_with_smtp(envelope: &Envelope, raw_email: &[u8]) -> EmptyResult {
+    match mailer().send_raw(envelope, raw_email) {
         Ok(_) => Ok(()),
         // Match some common errors and make them more user friendly
         Err(e) => match e {
@@ -350,3 +664,276 @@ fn send_email(address: &str, subject: &str, body_html: String, body_text: String
         }
     }
 }
+
+fn send_with_sendmail(envelope: &Envelope, raw_email: &[u8]) -> EmptyResult {
+    match SendmailTransport::new_with_command(CONFIG.sendmail_command()).send_raw(envelope, raw_email) {
+        Ok(_) => Ok(()),
+        Err(e) => err!(format!("Sendmail error: {}", e)),
+    }
+}
+
+/// Writes the email as an .eml file, instead of sending it
+fn save_to_file(raw_email: &[u8]) -> EmptyResult {
+    let folder = CONFIG.mail_file_folder();
+    std::fs::create_dir_all(&folder)?;
+
+    let file_name = format!("{}_{}.eml", Local::now().format("%Y%m%d%H%M%S"), crate::util::get_uuid());
+    std::fs::write(Path::new(&folder).join(file_name), raw_email)?;
+    Ok(())
+}
+
+/// Renders an email template with example values, used to preview them from the admin page
+pub fn preview_email(template_name: &str) -> Result<(String, String, String), Error> {
+    let template_name = match PREVIEW_TEMPLATES.iter().find(|t| **t == template_name) {
+        Some(t) => *t,
+        None => err!("Unknown email template"),
+    };
+
+    get_text(
+        &format!("email/{}", template_name),
+        User::CULTURE_DEFAULT,
+        json!({
+            "url": CONFIG.domain(),
+            "user_id": "00000000-0000-0000-0000-000000000000",
+            "org_id": "00000000-0000-0000-0000-000000000000",
+            "org_user_id": "00000000-0000-0000-0000-000000000000",
+            "email": "user@example.com",
+            "org_name": CONFIG.invitation_org_name(),
+            "token": "123456",
+            "hint": "Example password hint",
+            "ip": "192.0.2.1",
+            "device": "Firefox",
+            "datetime": crate::util::format_datetime_local(&Local::now(), DATETIME_FORMAT),
+            "old_email": "old@example.com",
+            "new_email": "user@example.com",
+            "provider": "Authenticator app",
+            "kdf": "PBKDF2-SHA256",
+            "kdf_iterations": User::CLIENT_KDF_ITER_DEFAULT,
+            "attempts": CONFIG.security_notification_failed_2fa(),
+            "action": "disabled",
+            "unsubscribe_url": format!("{}/api/accounts/security-notifications/unsubscribe?token=", CONFIG.domain()),
+        }),
+    )
+}
+
+pub const PREVIEW_TEMPLATES: &[&str] = &[
+    "account_exists",
+    "change_email",
+    "delete_account",
+    "invite_accepted",
+    "invite_confirmed",
+    "new_device_logged_in",
+    "pw_hint_none",
+    "pw_hint_some",
+    "security_admin_action",
+    "security_email_changed",
+    "security_failed_twofactor",
+    "security_kdf_changed",
+    "security_key_rotated",
+    "security_password_changed",
+    "security_twofactor_added",
+    "security_twofactor_removed",
+    "send_org_invite",
+    "smtp_test",
+    "twofactor_email",
+    "verify_email",
+    "welcome",
+    "welcome_must_verify",
+];
+
+//
+// DKIM signing
+//
+// lettre doesn't support DKIM yet, so the formatted message is signed here, using the "relaxed" canonicalization
+// for both the headers and the body (RFC 6376). The body contains both the text and HTML parts, so both are covered.
+struct DkimConfig {
+    selector: String,
+    domain: String,
+    private_key: PKey<Private>,
+}
+
+// Headers included in the signature, when present
+const DKIM_SIGNED_HEADERS: &[&str] = &["from", "to", "subject", "date", "message-id", "mime-version", "content-type"];
+
+fn dkim_config() -> Option<DkimConfig> {
+    let (selector, domain, key_file) = match (CONFIG.dkim_selector(), CONFIG.dkim_domain(), CONFIG.dkim_private_key_file()) {
+        (Some(selector), Some(domain), Some(key_file)) => (selector, domain, key_file),
+        _ => return None,
+    };
+
+    // The key is read every time, so it can be rotated without restarting the server
+    let private_key = match read_file(&key_file).map_err(Error::from).and_then(|pem| Ok(PKey::private_key_from_pem(&pem)?)) {
+        Ok(key) => key,
+        Err(e) => {
+            error!("Error loading DKIM private key, sending email unsigned: {:?}", e);
+            return None;
+        }
+    };
+
+    Some(DkimConfig {
+        selector,
+        domain,
+        private_key,
+    })
+}
+
+/// Returns the raw email with a DKIM-Signature header added at the top
+fn dkim_sign(raw_email: &[u8], dkim: &DkimConfig) -> Result<Vec<u8>, Error> {
+    let (headers, body) = match raw_email.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => (&raw_email[..pos + 2], &raw_email[pos + 4..]),
+        None => err!("Invalid email format"),
+    };
+    let headers = String::from_utf8_lossy(headers);
+
+    let body_hash = BASE64.encode(digest::digest(&digest::SHA256, &dkim_canonicalize_body(body)).as_ref());
+
+    let mut signed_names = Vec::new();
+    let mut signed_data = Vec::new();
+    let parsed_headers = dkim_parse_headers(&headers);
+    for name in DKIM_SIGNED_HEADERS {
+        // When a header is repeated, the last one is signed
+        if let Some((_, value)) = parsed_headers.iter().rev().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+            signed_data.extend_from_slice(dkim_canonicalize_header(name, value).as_bytes());
+            signed_data.extend_from_slice(b"\r\n");
+            signed_names.push(*name);
+        }
+    }
+
+    let signature_value = format!(
+        "v=1; a=rsa-sha256; c=relaxed/relaxed; d={}; s={}; t={}; h={}; bh={}; b=",
+        dkim.domain,
+        dkim.selector,
+        chrono::Utc::now().timestamp(),
+        signed_names.join(":"),
+        body_hash
+    );
+    // The signature header itself is signed too, with an empty b= value and without the trailing CRLF
+    signed_data.extend_from_slice(dkim_canonicalize_header("dkim-signature", &signature_value).as_bytes());
+
+    let mut signer = Signer::new(MessageDigest::sha256(), &dkim.private_key)?;
+    signer.update(&signed_data)?;
+    let signature = BASE64.encode(&signer.sign_to_vec()?);
+
+    let mut signed_email = format!("DKIM-Signature: {}{}\r\n", signature_value, signature).into_bytes();
+    signed_email.extend_from_slice(raw_email);
+    Ok(signed_email)
+}
+
+/// Splits the header block in (name, value) pairs, keeping the folded values as they are
+fn dkim_parse_headers(headers: &str) -> Vec<(String, String)> {
+    let mut parsed: Vec<(String, String)> = Vec::new();
+    for line in headers.split("\r\n").filter(|l| !l.is_empty()) {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some((_, value)) = parsed.last_mut() {
+                value.push_str("\r\n");
+                value.push_str(line);
+            }
+        } else if let Some(pos) = line.find(':') {
+            parsed.push((line[..pos].to_string(), line[pos + 1..].to_string()));
+        }
+    }
+    parsed
+}
+
+/// Collapses all the whitespace sequences (including line folding) into a single space
+fn dkim_collapse_whitespace(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut in_whitespace = false;
+    for c in value.chars() {
+        if c == ' ' || c == '\t' || c == '\r' || c == '\n' {
+            in_whitespace = true;
+        } else {
+            if in_whitespace && !result.is_empty() {
+                result.push(' ');
+            }
+            in_whitespace = false;
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn dkim_canonicalize_header(name: &str, value: &str) -> String {
+    format!("{}:{}", name.trim().to_lowercase(), dkim_collapse_whitespace(value))
+}
+
+fn dkim_canonicalize_body(body: &[u8]) -> Vec<u8> {
+    let body = String::from_utf8_lossy(body);
+    let mut lines: Vec<String> = body
+        .split("\r\n")
+        .map(|line| {
+            // Whitespace is reduced to a single space, and removed at the end of the line
+            let mut canonical = String::with_capacity(line.len());
+            let mut in_whitespace = false;
+            for c in line.chars() {
+                if c == ' ' || c == '\t' {
+                    in_whitespace = true;
+                } else {
+                    if in_whitespace {
+                        canonical.push(' ');
+                    }
+                    in_whitespace = false;
+                    canonical.push(c);
+                }
+            }
+            canonical
+        })
+        .collect();
+
+    // Empty lines at the end of the body are ignored
+    while lines.last().map_or(false, |l| l.is_empty()) {
+        lines.pop();
+    }
+
+    let mut canonical = Vec::with_capacity(body.len());
+    for line in lines {
+        canonical.extend_from_slice(line.as_bytes());
+        canonical.extend_from_slice(b"\r\n");
+    }
+    canonical
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dkim_canonicalization() {
+        // Example from RFC 6376, section 3.4.5
+        let headers = dkim_parse_headers("A: X\r\nB : Y\t\r\n\tZ  \r\n");
+        let canonical: Vec<String> = headers.iter().map(|(n, v)| dkim_canonicalize_header(n, v)).collect();
+        assert_eq!(canonical, vec!["a:X", "b:Y Z"]);
+
+        assert_eq!(dkim_canonicalize_body(b" C \r\nD \t E\r\n\r\n\r\n"), b" C\r\nD E\r\n");
+        assert_eq!(dkim_canonicalize_body(b""), b"");
+    }
+
+    #[test]
+    fn test_dkim_signature() {
+        use openssl::{rsa::Rsa, sign::Verifier};
+
+        let dkim = DkimConfig {
+            selector: "mail".to_string(),
+            domain: "example.com".to_string(),
+            private_key: PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap(),
+        };
+
+        let raw_email = b"From: Bitwarden_RS <bw@example.com>\r\nTo: user@example.com\r\nSubject: Test\r\n\r\nBody  text\r\n\r\n";
+        let signed = String::from_utf8(dkim_sign(raw_email, &dkim).unwrap()).unwrap();
+        assert!(signed.ends_with(std::str::from_utf8(raw_email).unwrap()));
+
+        let header = signed.split("\r\n").next().unwrap();
+        let value = header.trim_start_matches("DKIM-Signature: ");
+        assert!(value.contains("d=example.com; s=mail;"));
+        assert!(value.contains("h=from:to:subject;"));
+
+        let (unsigned_value, signature) = value.split_at(value.rfind("b=").unwrap() + 2);
+        let signed_data = format!(
+            "from:Bitwarden_RS <bw@example.com>\r\nto:user@example.com\r\nsubject:Test\r\ndkim-signature:{}",
+            unsigned_value
+        );
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &dkim.private_key).unwrap();
+        verifier.update(signed_data.as_bytes()).unwrap();
+        assert!(verifier.verify(&BASE64.decode(signature.as_bytes()).unwrap()).unwrap());
+    }
+}
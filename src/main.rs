@@ -34,6 +34,7 @@ mod crypto;
 #[macro_use]
 mod db;
 mod mail;
+mod storage;
 mod util;
 
 pub use config::CONFIG;
@@ -58,6 +59,7 @@ fn main() {
     check_web_vault();
 
     create_icon_cache_folder();
+    create_dir(&CONFIG.tmp_folder(), "temporary files");
 
     launch_rocket(extra_debug);
 }
@@ -71,6 +73,16 @@ const HELP: &str = "\
         FLAGS:
             -h, --help       Prints help information
             -v, --version    Prints the app version
+
+        COMMANDS:
+            rotate-jwt-key   Creates a new key to sign the tokens. The current one is still accepted
+                             for JWT_KEY_GRACE_PERIOD seconds, or until the access tokens it signed
+                             expire. Restart the server to use the new key
+
+        OPTIONS:
+            --revoke         With rotate-jwt-key, stops accepting the current key as soon as the server
+                             is restarted. Use it if the key was compromised. The clients have to get new
+                             access tokens, and the links sent by email stop working
 ";
 
 fn parse_args() {
@@ -85,6 +97,23 @@ fn parse_args() {
         println!("bitwarden_rs {}", option_env!("BWRS_VERSION").unwrap_or(NO_VERSION));
         exit(0);
     }
+
+    match pargs.subcommand() {
+        Ok(None) => (),
+        Ok(Some(command)) if command == "rotate-jwt-key" => {
+            rotate_rsa_keys(pargs.contains("--revoke"));
+            exit(0);
+        }
+        Ok(Some(command)) => {
+            println!("Unknown command: {}", command);
+            print!("{}", HELP);
+            exit(1);
+        }
+        Err(e) => {
+            println!("Error parsing the arguments: {}", e);
+            exit(1);
+        }
+    }
 }
 
 fn launch_info() {
@@ -255,32 +284,7 @@ fn check_rsa_keys() {
 
         info!("OpenSSL detected, creating keys...");
 
-        let key = CONFIG.rsa_key_filename();
-
-        let pem = format!("{}.pem", key);
-        let priv_der = format!("{}.der", key);
-        let pub_der = format!("{}.pub.der", key);
-
-        let mut success = Command::new("openssl")
-            .args(&["genrsa", "-out", &pem])
-            .status()
-            .expect("Failed to create private pem file")
-            .success();
-
-        success &= Command::new("openssl")
-            .args(&["rsa", "-in", &pem, "-outform", "DER", "-out", &priv_der])
-            .status()
-            .expect("Failed to create private der file")
-            .success();
-
-        success &= Command::new("openssl")
-            .args(&["rsa", "-in", &priv_der, "-inform", "DER"])
-            .args(&["-RSAPublicKey_out", "-outform", "DER", "-out", &pub_der])
-            .status()
-            .expect("Failed to create public der file")
-            .success();
-
-        if success {
+        if create_rsa_keys() {
             info!("Keys created correctly.");
         } else {
             error!("Error creating keys, exiting...");
@@ -289,6 +293,63 @@ fn check_rsa_keys() {
     }
 }
 
+/// Creates the JWT signing keys with OpenSSL, overwriting the current ones. Returns true on success
+fn create_rsa_keys() -> bool {
+    let key = CONFIG.rsa_key_filename();
+
+    let pem = format!("{}.pem", key);
+    let priv_der = format!("{}.der", key);
+    let pub_der = format!("{}.pub.der", key);
+
+    let mut success = Command::new("openssl")
+        .args(&["genrsa", "-out", &pem])
+        .status()
+        .expect("Failed to create private pem file")
+        .success();
+
+    success &= Command::new("openssl")
+        .args(&["rsa", "-in", &pem, "-outform", "DER", "-out", &priv_der])
+        .status()
+        .expect("Failed to create private der file")
+        .success();
+
+    success &= Command::new("openssl")
+        .args(&["rsa", "-in", &priv_der, "-inform", "DER"])
+        .args(&["-RSAPublicKey_out", "-outform", "DER", "-out", &pub_der])
+        .status()
+        .expect("Failed to create public der file")
+        .success();
+
+    success
+}
+
+/// Replaces the JWT signing keys, keeping the public part of the current ones to verify the tokens it signed,
+/// unless they are revoked
+fn rotate_rsa_keys(revoke: bool) {
+    if revoke {
+        println!("The current key will not be accepted anymore");
+    } else if util::file_exists(&CONFIG.public_rsa_key()) {
+        match auth::retire_current_rsa_key() {
+            Ok(kid) => println!(
+                "The current key {} will be accepted for up to {} more seconds",
+                kid,
+                CONFIG.jwt_key_grace_period()
+            ),
+            Err(e) => {
+                println!("Error retiring the current key: {:?}", e);
+                exit(1);
+            }
+        }
+    }
+
+    if create_rsa_keys() {
+        println!("New key created, restart the server to start using it");
+    } else {
+        println!("Error creating the new key");
+        exit(1);
+    }
+}
+
 fn check_web_vault() {
     if !CONFIG.web_vault_enabled() {
         return;
@@ -313,6 +374,10 @@ fn launch_rocket(extra_debug: bool) {
         }
     };
 
+    mail::start_queue_workers(pool.clone());
+    api::start_icon_cache_sweeper();
+    api::start_login_history_purge(pool.clone());
+
     let basepath = &CONFIG.domain_path();
 
     // If adding more paths here, consider also adding them to
@@ -324,8 +389,8 @@ fn launch_rocket(extra_debug: bool) {
         .mount(&[basepath, "/identity"].concat(), api::identity_routes())
         .mount(&[basepath, "/icons"].concat(), api::icons_routes())
         .mount(&[basepath, "/notifications"].concat(), api::notifications_routes())
+        .manage(api::start_notification_server(pool.clone()))
         .manage(pool)
-        .manage(api::start_notification_server())
         .attach(util::AppHeaders())
         .attach(util::CORS())
         .attach(util::BetterLogging(extra_debug))
@@ -6,9 +6,11 @@ use std::io::Cursor;
 use rocket::{
     fairing::{Fairing, Info, Kind},
     http::{ContentType, Header, HeaderMap, Method, Status},
+    request::{self, FromRequest},
     response::{self, Responder},
     Data, Request, Response, Rocket,
 };
+use serde_json::Value;
 
 use crate::CONFIG;
 
@@ -28,7 +30,11 @@ impl Fairing for AppHeaders {
         res.set_raw_header("X-Frame-Options", "SAMEORIGIN");
         res.set_raw_header("X-Content-Type-Options", "nosniff");
         res.set_raw_header("X-XSS-Protection", "1; mode=block");
-        let csp = format!("frame-ancestors 'self' chrome-extension://nngceckbapebfimnlniiiahkandclblb moz-extension://* {};", CONFIG.allowed_iframe_ancestors());
+        let mut csp = format!("frame-ancestors 'self' chrome-extension://nngceckbapebfimnlniiiahkandclblb moz-extension://* {};", CONFIG.allowed_iframe_ancestors());
+        // SVG images can contain scripts, don't let them run if they are opened directly
+        if res.content_type() == Some(ContentType::SVG) {
+            csp.push_str(" default-src 'none'; style-src 'unsafe-inline'; sandbox;");
+        }
         res.set_raw_header("Content-Security-Policy", csp);
 
         // Disable cache unless otherwise specified
@@ -112,6 +118,133 @@ impl<'r, R: Responder<'r>> Responder<'r> for Cached<R> {
     }
 }
 
+//
+// Conditional and compressed responses
+//
+
+// Responses smaller than this are not worth compressing
+const GZIP_MIN_SIZE: usize = 1024;
+
+/// Request guard with the headers needed to answer with a `ConditionalJson`
+pub struct ConditionalHeaders {
+    if_none_match: Option<String>,
+    accepts_gzip: bool,
+}
+
+impl ConditionalHeaders {
+    /// Returns true if the client already has the version of the resource identified by `etag`.
+    /// Weak comparison is used, as the ETags generated by `ConditionalJson` are weak.
+    pub fn is_not_modified(&self, etag: &str) -> bool {
+        match &self.if_none_match {
+            Some(if_none_match) => if_none_match.split(',').map(str::trim).any(|tag| {
+                tag == "*" || tag.trim_start_matches("W/") == etag.trim_start_matches("W/")
+            }),
+            None => false,
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ConditionalHeaders {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let headers = request.headers();
+
+        let if_none_match = headers.get_one("If-None-Match").map(String::from);
+
+        // Ignore the encodings explicitly marked as not acceptable (q=0)
+        let accepts_gzip = headers.get("Accept-Encoding").flat_map(|h| h.split(',')).any(|encoding| {
+            let mut parts = encoding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let rejected = parts.any(|p| p.strip_prefix("q=").and_then(|q| q.trim().parse::<f32>().ok()) == Some(0.0));
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !rejected
+        });
+
+        request::Outcome::Success(ConditionalHeaders {
+            if_none_match,
+            accepts_gzip,
+        })
+    }
+}
+
+/// JSON response tagged with a (weak) ETag. When the client already has the same version,
+/// the body is skipped and a `304 Not Modified` is returned instead.
+pub enum ConditionalJson {
+    NotModified(String),
+    Modified {
+        etag: String,
+        value: Value,
+        gzip: bool,
+    },
+}
+
+impl ConditionalJson {
+    /// Builds the response for the given ETag value, the value is quoted and marked as weak here.
+    /// `value` is only called when the client doesn't have the current version.
+    pub fn new<F, E>(tag: &str, headers: &ConditionalHeaders, value: F) -> Result<Self, E>
+    where
+        F: FnOnce() -> Result<Value, E>,
+    {
+        let etag = format!("W/\"{}\"", tag);
+
+        if headers.is_not_modified(&etag) {
+            Ok(ConditionalJson::NotModified(etag))
+        } else {
+            Ok(ConditionalJson::Modified {
+                etag,
+                value: value()?,
+                gzip: headers.accepts_gzip,
+            })
+        }
+    }
+}
+
+impl<'r> Responder<'r> for ConditionalJson {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        // The clients should always revalidate, but they are allowed to store the response
+        const CACHE_CONTROL: &str = "private, no-cache";
+
+        match self {
+            ConditionalJson::NotModified(etag) => Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", etag)
+                .raw_header("Cache-Control", CACHE_CONTROL)
+                .ok(),
+            ConditionalJson::Modified {
+                etag,
+                value,
+                gzip,
+            } => {
+                let body = serde_json::to_vec(&value).map_err(|e| {
+                    error!("Error serializing response: {:#?}", e);
+                    Status::InternalServerError
+                })?;
+
+                let mut res = Response::build();
+                res.header(ContentType::JSON)
+                    .raw_header("ETag", etag)
+                    .raw_header("Cache-Control", CACHE_CONTROL)
+                    .raw_header("Vary", "Accept-Encoding");
+
+                if gzip && body.len() >= GZIP_MIN_SIZE {
+                    use flate2::{write::GzEncoder, Compression};
+                    use std::io::Write;
+
+                    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                    match encoder.write_all(&body).and_then(|_| encoder.finish()) {
+                        Ok(compressed) => {
+                            return res.raw_header("Content-Encoding", "gzip").sized_body(Cursor::new(compressed)).ok()
+                        }
+                        Err(e) => warn!("Error compressing response, sending it uncompressed: {:#?}", e),
+                    }
+                }
+
+                res.sized_body(Cursor::new(body)).ok()
+            }
+        }
+    }
+}
+
 // Log all the routes from the main paths list, and the attachments endpoint
 // Effectively ignores, any static file route, and the alive endpoint
 const LOGGED_ROUTES: [&str; 6] = [
@@ -231,8 +364,8 @@ pub fn delete_file(path: &str) -> IOResult<()> {
 
 const UNITS: [&str; 6] = ["bytes", "KB", "MB", "GB", "TB", "PB"];
 
-pub fn get_display_size(size: i32) -> String {
-    let mut size: f64 = size.into();
+pub fn get_display_size(size: i64) -> String {
+    let mut size = size as f64;
     let mut unit_counter = 0;
 
     loop {
@@ -374,7 +507,6 @@ pub fn is_running_in_docker() -> bool {
 use std::fmt;
 
 use serde::de::{self, DeserializeOwned, Deserializer, MapAccess, SeqAccess, Visitor};
-use serde_json::{self, Value};
 
 pub type JsonMap = serde_json::Map<String, Value>;
 